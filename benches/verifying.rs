@@ -6,7 +6,7 @@ use post::{
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
     pow::randomx::{PoW, RandomXFlag},
-    prove::generate_proof,
+    prove::{generate_proof, Nonces, ProvingOptions},
     verification::Verifier,
 };
 #[cfg(not(windows))]
@@ -45,11 +45,23 @@ fn verifying(c: &mut Criterion) {
     let pow_flags = RandomXFlag::get_recommended_flags();
     // Generate a proof
     let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
     let metadata = ProofMetadata::new(metadata, *challenge);
 
     // Bench verifying the proof
-    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
     c.bench_function("verify", |b| {
         b.iter(|| {
             verifier
@@ -0,0 +1,57 @@
+//! Storage audits of POST services
+//!
+//! Requests random labels from the audit server of a POST service
+//! and checks them against the identity's POST metadata (see [post::audit]).
+//! The labels must arrive before a deadline, by default [post::audit::deadline]
+//! (see [post::audit] for what it proves).
+
+use std::time::{Duration, Instant};
+
+use eyre::Context;
+use post::audit::{AuditRequest, AuditResponse};
+use post::config::InitConfig;
+use post::metadata::ProofMetadata;
+
+/// Audit the POS data described by `metadata` held by the POST service
+/// serving audits on `url` (i.e. "http://localhost:9000") by checking `labels` random labels.
+/// The labels must arrive within `deadline`.
+pub async fn audit(
+    client: &reqwest::Client,
+    url: &str,
+    metadata: ProofMetadata,
+    init_cfg: InitConfig,
+    labels: usize,
+    deadline: Duration,
+) -> eyre::Result<()> {
+    let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
+    let request = AuditRequest::random(num_labels, labels)?;
+    tracing::debug!("auditing {labels} labels on {url} (deadline: {deadline:?})");
+
+    let start = Instant::now();
+    let response = tokio::time::timeout(deadline, async {
+        let response = client
+            .post(format!("{url}/audit"))
+            .json(&request)
+            .send()
+            .await
+            .wrap_err("sending audit request")?;
+        let status = response.status();
+        if !status.is_success() {
+            let reason = response.text().await.unwrap_or_default();
+            eyre::bail!("audit request failed ({status}): {reason}");
+        }
+        response
+            .json::<AuditResponse>()
+            .await
+            .wrap_err("decoding audit response")
+    })
+    .await
+    .map_err(|_| eyre::eyre!("audit failed: no response within {deadline:?}"))??;
+    tracing::debug!("audit response took {:?}", start.elapsed());
+
+    tokio::task::spawn_blocking(move || {
+        post::audit::verify(&request, &response, &metadata, &init_cfg)
+    })
+    .await?
+    .wrap_err("audit failed")
+}
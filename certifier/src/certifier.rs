@@ -73,11 +73,14 @@ pub fn new(
     init_cfg: InitConfig,
     signer: SigningKey,
     randomx_mode: RandomXMode,
+    verifying_threads: usize,
 ) -> Router {
     let state = AppState {
-        verifier: Verifier::new(Box::new(
-            PoW::new(randomx_mode.into()).expect("creating RandomX PoW verifier"),
-        )),
+        verifier: Verifier::new(
+            Box::new(PoW::new(randomx_mode.into()).expect("creating RandomX PoW verifier")),
+            verifying_threads,
+        )
+        .expect("creating verifier"),
         cfg,
         init_cfg,
         signer,
@@ -27,6 +27,10 @@ impl From<RandomXMode> for RandomXFlag {
     }
 }
 
+fn default_verifying_threads() -> usize {
+    1
+}
+
 fn max_concurrency() -> usize {
     std::thread::available_parallelism()
         .expect("fetching number of cores")
@@ -54,6 +58,11 @@ pub struct Config {
     #[serde(default)]
     pub randomx_mode: RandomXMode,
 
+    /// The number of threads to regenerate K3 labels of a single proof with.
+    /// '0' means all available threads. Defaults to 1 (no parallelism within a request).
+    #[serde(default = "default_verifying_threads")]
+    pub verifying_threads: usize,
+
     /// Address to expose metrics on.
     /// Metrics are disabled if not configured.
     pub metrics: Option<std::net::SocketAddr>,
@@ -1,4 +1,4 @@
-use std::{future::IntoFuture, path::PathBuf};
+use std::{future::IntoFuture, path::PathBuf, time::Duration};
 
 use axum::routing::get;
 use axum_prometheus::PrometheusMetricLayerBuilder;
@@ -31,6 +31,24 @@ enum Commands {
     /// generate keypair and write it to standard out.
     /// the keypair is encoded as json
     GenerateKeys,
+    /// audit that a POST service still holds its POS data
+    /// by checking random labels read by its audit server.
+    Audit {
+        /// address of the audit server of the POST service (i.e. "http://localhost:9000")
+        #[arg(long)]
+        url: String,
+        /// file with the POST metadata of the audited identity (as sent to /certify)
+        #[arg(long)]
+        metadata: PathBuf,
+        /// number of random labels to check
+        #[arg(long, default_value_t = 64)]
+        labels: usize,
+        /// time (in milliseconds) the labels must arrive within
+        ///
+        /// Defaults to 1s and 15ms (a random disk read) for every label.
+        #[arg(long)]
+        deadline_ms: Option<u64>,
+    },
 }
 
 fn generate_keys() -> Result<(), Box<dyn std::error::Error>> {
@@ -58,8 +76,29 @@ fn generate_keys() -> Result<(), Box<dyn std::error::Error>> {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
 
-    if let Some(Commands::GenerateKeys) = args.cmd {
-        return generate_keys();
+    match args.cmd {
+        Some(Commands::GenerateKeys) => return generate_keys(),
+        Some(Commands::Audit {
+            url,
+            metadata,
+            labels,
+            deadline_ms,
+        }) => {
+            let config = certifier::configuration::get_configuration(&args.config)?;
+            let metadata = serde_json::from_reader(std::fs::File::open(metadata)?)?;
+            certifier::audit::audit(
+                &reqwest::Client::new(),
+                &url,
+                metadata,
+                config.init_cfg,
+                labels,
+                deadline_ms.map_or(post::audit::deadline(labels), Duration::from_millis),
+            )
+            .await?;
+            println!("audit passed");
+            return Ok(());
+        }
+        None => {}
     }
 
     LogTracer::init()?;
@@ -77,6 +116,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("POST proof configuration: {:?}", config.post_cfg);
     info!("POST init configuration: {:?}", config.init_cfg);
     info!("RandomX mode: {:?}", config.randomx_mode);
+    info!("verifying threads: {}", config.verifying_threads);
     info!(
         "max concurrent requests: {}",
         config.max_concurrent_requests
@@ -87,6 +127,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.init_cfg,
         signer,
         config.randomx_mode,
+        config.verifying_threads,
     )
     .layer(ConcurrencyLimitLayer::new(config.max_concurrent_requests));
 
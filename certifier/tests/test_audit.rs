@@ -0,0 +1,81 @@
+use std::{future::IntoFuture, path::PathBuf, time::Duration};
+
+use axum::{extract::State, routing::post, Json, Router};
+use post::{
+    audit::{AuditRequest, AuditResponse},
+    config::{InitConfig, ScryptParams},
+    initialize::{CpuInitializer, Initialize},
+    metadata::ProofMetadata,
+};
+use tokio::net::TcpListener;
+
+/// Serve audits of POS data in the given directory,
+/// replacing the labels with zeros if `tamper` is set
+/// and answering after `delay`.
+async fn serve_audits(datadir: PathBuf, tamper: bool, delay: Duration) -> String {
+    async fn audit(
+        State((datadir, tamper, delay)): State<(PathBuf, bool, Duration)>,
+        Json(request): Json<AuditRequest>,
+    ) -> Json<AuditResponse> {
+        tokio::time::sleep(delay).await;
+        let mut response = post::audit::respond(&datadir, &request).unwrap();
+        if tamper {
+            response.labels.fill([0; 16]);
+        }
+        Json(response)
+    }
+
+    let app = Router::new()
+        .route("/audit", post(audit))
+        .with_state((datadir, tamper, delay));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, app.into_make_service()).into_future());
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn test_audit_post_service() {
+    let datadir = tempfile::tempdir().unwrap();
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+    let metadata = ProofMetadata::new(metadata, [0; 32]);
+    let client = reqwest::Client::new();
+
+    let deadline = post::audit::deadline(16);
+    let url = serve_audits(datadir.path().into(), false, Duration::ZERO).await;
+    certifier::audit::audit(&client, &url, metadata.clone(), init_cfg, 16, deadline)
+        .await
+        .expect("audit should pass");
+
+    let url = serve_audits(datadir.path().into(), true, Duration::ZERO).await;
+    assert!(
+        certifier::audit::audit(&client, &url, metadata.clone(), init_cfg, 16, deadline)
+            .await
+            .is_err()
+    );
+
+    // Valid labels, but too late
+    let deadline = Duration::from_millis(100);
+    let url = serve_audits(datadir.path().into(), false, deadline * 3).await;
+    assert!(
+        certifier::audit::audit(&client, &url, metadata, init_cfg, 16, deadline)
+            .await
+            .is_err()
+    );
+}
@@ -6,8 +6,8 @@ use post::{
     config::{InitConfig, ProofConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
-    pow::randomx::RandomXFlag,
-    prove::generate_proof,
+    pow::randomx::{PoW, RandomXFlag},
+    prove::{generate_proof, Nonces, ProvingOptions},
 };
 use reqwest::StatusCode;
 use tokio::net::TcpListener;
@@ -46,12 +46,24 @@ async fn test_certificate_post_proof() {
     // Generate a proof
     let pow_flags = RandomXFlag::get_recommended_flags();
     let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
     let metadata = ProofMetadata::new(metadata, *challenge);
 
     // Spawn the certifier service
     let signer = SigningKey::generate(&mut rand::rngs::OsRng);
-    let app = certifier::certifier::new(cfg, init_cfg, signer, RandomXMode::Light);
+    let app = certifier::certifier::new(cfg, init_cfg, signer, RandomXMode::Light, 2);
     let listener = TcpListener::bind(SocketAddr::from_str("127.0.0.1:0").unwrap())
         .await
         .unwrap();
@@ -0,0 +1,220 @@
+//! Command line arguments shared by the POST tools
+//!
+//! The network parameters, the RandomX mode and the number of nonces
+//! are given the same way to the POST service, post-cli, the k2pow service
+//! and the distributed proving coordinator. The selection of labels to verify
+//! is given the same way to post-cli and the initializer.
+
+use clap::{Args, ValueEnum};
+use eyre::Context;
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    pos_verification::SamplingSeed,
+    pow::randomx::RandomXFlag,
+    prove::Nonces,
+};
+
+/// POST configuration - network parameters
+#[derive(Args, Debug)]
+pub struct PostConfig {
+    #[command(flatten)]
+    pub proof: ProofConfigArgs,
+    #[command(flatten)]
+    pub init: InitConfigArgs,
+}
+
+/// Proving parameters - network parameters
+#[derive(Args, Debug)]
+pub struct ProofConfigArgs {
+    /// K1 specifies the difficulty for a label to be a candidate for a proof
+    #[arg(long, default_value_t = 26)]
+    pub k1: u32,
+    /// K2 is the number of labels below the required difficulty required for a proof
+    #[arg(long, default_value_t = 37)]
+    pub k2: u32,
+    /// K3 is the size of the subset of proof indices that is validated
+    #[arg(long, default_value_t = 37)]
+    pub k3: u32,
+    /// difficulty for the nonce proof of work (aka "k2pow")
+    #[arg(
+        long,
+        default_value = "000dfb23b0979b4b000000000000000000000000000000000000000000000000",
+        value_parser(parse_difficulty)
+    )]
+    pub pow_difficulty: [u8; 32],
+}
+
+impl From<&ProofConfigArgs> for ProofConfig {
+    fn from(args: &ProofConfigArgs) -> Self {
+        ProofConfig {
+            k1: args.k1,
+            k2: args.k2,
+            k3: args.k3,
+            pow_difficulty: args.pow_difficulty,
+        }
+    }
+}
+
+/// Initialization parameters - network parameters
+#[derive(Args, Debug)]
+pub struct InitConfigArgs {
+    /// The minimal number of units that must be initialized.
+    #[arg(long, default_value_t = 4)]
+    pub min_num_units: u32,
+    /// The maximal number of units that can be initialized.
+    #[arg(long, default_value_t = u32::MAX)]
+    pub max_num_units: u32,
+    ///  The number of labels per unit.
+    #[arg(long, default_value_t = 4294967296)]
+    pub labels_per_unit: u64,
+    /// scrypt parameters for initialization
+    #[command(flatten)]
+    pub scrypt: ScryptArgs,
+}
+
+impl From<&InitConfigArgs> for InitConfig {
+    fn from(args: &InitConfigArgs) -> Self {
+        InitConfig {
+            min_num_units: args.min_num_units,
+            max_num_units: args.max_num_units,
+            labels_per_unit: args.labels_per_unit,
+            scrypt: ScryptParams::new(args.scrypt.n, args.scrypt.r, args.scrypt.p),
+        }
+    }
+}
+
+/// Scrypt parameters for initialization
+#[derive(Args, Debug)]
+pub struct ScryptArgs {
+    /// scrypt N parameter
+    #[arg(short, default_value_t = 8192)]
+    pub n: usize,
+    /// scrypt R parameter
+    #[arg(short, default_value_t = 1)]
+    pub r: usize,
+    /// scrypt P parameter
+    #[arg(short, default_value_t = 1)]
+    pub p: usize,
+}
+
+/// Selection of labels to verify
+#[derive(Args, Debug)]
+pub struct SamplingArgs {
+    /// Hex-encoded seed (32 bytes) selecting labels to verify.
+    /// Verification with the same seed checks the same labels.
+    /// If not given, a random one is used.
+    #[arg(long, conflicts_with = "challenge")]
+    pub seed: Option<SamplingSeed>,
+    /// Challenge (any string, i.e. a block hash) to derive the seed selecting labels to verify from
+    #[arg(long)]
+    pub challenge: Option<String>,
+}
+
+impl SamplingArgs {
+    /// The seed given, derived from the challenge or a random one.
+    pub fn seed(&self) -> SamplingSeed {
+        match (&self.seed, &self.challenge) {
+            (Some(seed), _) => *seed,
+            (None, Some(challenge)) => SamplingSeed::from_challenge(challenge.as_bytes()),
+            (None, None) => SamplingSeed::random(),
+        }
+    }
+}
+
+/// RandomX modes of operation
+///
+/// They are interchangeable as they give the same results but have different
+/// purpose and memory requirements.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub enum RandomXMode {
+    /// Fast mode for proving. Requires 2080 MiB of memory.
+    Fast,
+    /// Light mode for verification. Requires only 256 MiB of memory, but runs significantly slower
+    Light,
+}
+
+impl std::fmt::Display for RandomXMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
+impl From<RandomXMode> for RandomXFlag {
+    fn from(val: RandomXMode) -> Self {
+        match val {
+            RandomXMode::Fast => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
+            RandomXMode::Light => RandomXFlag::get_recommended_flags(),
+        }
+    }
+}
+
+/// Parse the number of nonces, either a fixed one (see [parse_fixed_nonces]) or 'auto'.
+pub fn parse_nonces(arg: &str) -> eyre::Result<Nonces> {
+    if arg == "auto" {
+        return Ok(Nonces::Auto);
+    }
+    parse_fixed_nonces(arg).map(Nonces::Fixed)
+}
+
+/// Parse the number of nonces, a positive multiple of 16.
+pub fn parse_fixed_nonces(arg: &str) -> eyre::Result<usize> {
+    let nonces = arg.parse()?;
+    eyre::ensure!(
+        nonces > 0 && nonces % 16 == 0,
+        "nonces must be a positive multiple of 16"
+    );
+    eyre::ensure!(nonces / 16 <= 256, format!("max nonces is {}", 256 * 16));
+    Ok(nonces)
+}
+
+pub fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
+    hex::decode(arg)?
+        .as_slice()
+        .try_into()
+        .wrap_err("invalid difficulty length")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_nonces() {
+        assert_eq!(Nonces::Auto, parse_nonces("auto").unwrap());
+        assert_eq!(Nonces::Fixed(32), parse_nonces("32").unwrap());
+        assert_eq!(256 * 16, parse_fixed_nonces("4096").unwrap());
+        assert!(parse_fixed_nonces("auto").is_err());
+        assert!(parse_fixed_nonces("0").is_err());
+        assert!(parse_fixed_nonces("17").is_err());
+        assert!(parse_fixed_nonces("4112").is_err());
+    }
+
+    #[test]
+    fn parsing_sampling_seed() {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            sampling: SamplingArgs,
+        }
+
+        let seed = "ab".repeat(32);
+        let args = Cli::try_parse_from(["cli", "--seed", &seed]).unwrap();
+        assert_eq!(SamplingSeed([0xAB; 32]), args.sampling.seed());
+
+        let args = Cli::try_parse_from(["cli", "--challenge", "block"]).unwrap();
+        assert_eq!(SamplingSeed::from_challenge(b"block"), args.sampling.seed());
+
+        assert!(Cli::try_parse_from(["cli", "--seed", &seed, "--challenge", "block"]).is_err());
+        assert!(Cli::try_parse_from(["cli", "--seed", "ab"]).is_err());
+        assert!(Cli::try_parse_from(["cli", "--seed", &format!("+f{}", "ab".repeat(31))]).is_err());
+    }
+
+    #[test]
+    fn parsing_difficulty() {
+        assert_eq!([0xFF; 32], parse_difficulty(&"ff".repeat(32)).unwrap());
+        assert!(parse_difficulty("ff").is_err());
+        assert!(parse_difficulty("not hex").is_err());
+    }
+}
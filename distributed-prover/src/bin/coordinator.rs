@@ -0,0 +1,94 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use cli_args::{parse_fixed_nonces, ProofConfigArgs, RandomXMode};
+use distributed_prover::coordinator::Coordinator;
+use eyre::Context;
+use k2pow_service::client::RemoteProver;
+use post::pow::{self, randomx::PoW};
+use tokio::net::TcpListener;
+
+/// Distributed proving coordinator
+///
+/// Generates proofs by splitting the POS data between workers.
+/// Calculates the k2pows (or fetches them from the k2pow service),
+/// hands ranges of labels out to the workers and merges the labels they found into a proof.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// directory of POST data
+    ///
+    /// Only the metadata file (postdata_metadata.json) is required.
+    #[arg(short, long)]
+    dir: PathBuf,
+    /// address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:3200")]
+    listen: SocketAddr,
+    /// address of a worker (i.e. "http://10.0.0.2:3100")
+    ///
+    /// Can be given many times, the POS data is split evenly between all workers.
+    #[arg(short, long = "worker", required = true)]
+    workers: Vec<String>,
+    /// number of nonces to attempt in single pass over POS data
+    ///
+    /// Each group of 16 nonces requires a separate PoW. Must be a multiple of 16 dividing 4096.
+    #[arg(long, default_value_t = 128, value_parser(parse_fixed_nonces))]
+    nonces: usize,
+    /// number of threads to calculate k2pow with
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// modes of operation for RandomX
+    #[arg(long, default_value_t = RandomXMode::Fast)]
+    randomx_mode: RandomXMode,
+    /// address of the k2pow service to delegate k2pow calculation to (i.e. "http://localhost:3000")
+    ///
+    /// If not set, k2pow is calculated locally.
+    #[arg(long)]
+    k2pow_service: Option<String>,
+
+    #[command(flatten, next_help_heading = "POST configuration")]
+    post_config: ProofConfigArgs,
+}
+
+fn main() -> eyre::Result<()> {
+    let args = Cli::parse();
+
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .wrap_err("building thread pool")?;
+
+    let metadata = post::metadata::load(&args.dir).wrap_err("loading metadata")?;
+    let cfg = (&args.post_config).into();
+    let pow_prover: Box<dyn pow::Prover + Send + Sync> = match args.k2pow_service {
+        Some(address) => {
+            log::info!("using k2pow service on {address}");
+            Box::new(RemoteProver::new(&address)?)
+        }
+        None => {
+            log::info!("initializing RandomX in {} mode", args.randomx_mode);
+            Box::new(PoW::new(args.randomx_mode.into()).wrap_err("creating RandomX PoW prover")?)
+        }
+    };
+    log::info!("splitting proving between workers: {:?}", args.workers);
+    let coordinator = Coordinator::new(metadata, cfg, args.nonces, args.workers, pow_prover)?;
+    coordinator
+        .check_workers()
+        .wrap_err("checking POS data of workers")?;
+
+    serve(args.listen, Arc::new(coordinator))
+}
+
+// The client of the k2pow service is blocking, so it's created outside of the async runtime.
+#[tokio::main]
+async fn serve(listen: SocketAddr, coordinator: Arc<Coordinator>) -> eyre::Result<()> {
+    log::info!("listening on: {listen}");
+    let app = distributed_prover::coordinator::router(coordinator);
+    let listener = TcpListener::bind(listen).await?;
+    axum::serve(listener, app.into_make_service()).await?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use eyre::Context;
+use post::reader::ReadSettings;
+use tokio::net::TcpListener;
+
+/// Distributed proving worker
+///
+/// Scans ranges of labels of POS data on request of the coordinator.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// directory of POST data
+    #[arg(short, long)]
+    dir: PathBuf,
+    /// address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:3100")]
+    listen: SocketAddr,
+    /// number of threads to use
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// size of a single read of POS data (in bytes)
+    ///
+    /// Must be a multiple of 128.
+    #[arg(long, default_value_t = ReadSettings::default().batch_size)]
+    read_batch_size: usize,
+    /// number of reads of POS data issued concurrently on each disk
+    #[arg(long, default_value_t = ReadSettings::default().queue_depth)]
+    read_queue_depth: usize,
+    /// read POS data bypassing the page cache (Linux only)
+    #[arg(long)]
+    direct_io: bool,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Cli::parse();
+
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    let read = ReadSettings {
+        batch_size: args.read_batch_size,
+        queue_depth: args.read_queue_depth,
+        direct_io: args.direct_io,
+    };
+    log::info!("opening POS data in {}", args.dir.display());
+    let worker = distributed_prover::worker::Worker::open(&args.dir, read, args.threads)
+        .wrap_err("opening POS data")?;
+
+    log::info!("listening on: {}", args.listen);
+    let app = distributed_prover::worker::router(Arc::new(worker));
+    let listener = TcpListener::bind(args.listen).await?;
+    axum::serve(listener, app.into_make_service()).await?;
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Clients of the coordinator and workers
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::Duration,
+};
+
+use post::{
+    metadata::PostMetadata,
+    prove::{PartialProof, Proof},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{CancelRequest, ProveRequest, ScanRequest};
+
+/// How often a proof requested from the coordinator checks if it should be stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Send the `request` to `url`, failing with the error message of the response on failure.
+fn send(
+    url: &str,
+    request: reqwest::blocking::RequestBuilder,
+) -> eyre::Result<reqwest::blocking::Response> {
+    let response = request.send()?;
+    let status = response.status();
+    if !status.is_success() {
+        eyre::bail!("{url} responded with {status}: {}", response.text()?);
+    }
+    Ok(response)
+}
+
+/// Send `request` to `url` and parse the response, including the error message on failure.
+fn post<Req: Serialize, Resp: DeserializeOwned>(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    request: &Req,
+) -> eyre::Result<Resp> {
+    Ok(send(url, client.post(url).json(request))?.json()?)
+}
+
+/// Client of a worker.
+#[derive(Debug, Clone)]
+pub struct WorkerClient {
+    address: String,
+    client: reqwest::blocking::Client,
+}
+
+impl WorkerClient {
+    /// Create a client of the worker available on `address` (i.e. "http://localhost:3100").
+    pub fn new(address: &str) -> eyre::Result<Self> {
+        // Scanning might take a long time.
+        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+        Ok(Self {
+            address: address.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    pub fn scan(&self, request: &ScanRequest) -> eyre::Result<PartialProof> {
+        log::debug!(
+            "requesting scan of labels {:?} from {}",
+            request.labels,
+            self.address
+        );
+        post(&self.client, &format!("{}/scan", self.address), request)
+    }
+
+    /// Get the metadata of the POS data held by the worker.
+    pub fn metadata(&self) -> eyre::Result<PostMetadata> {
+        let url = format!("{}/metadata", self.address);
+        Ok(send(&url, self.client.get(&url))?.json()?)
+    }
+
+    /// Stop the scans for `challenge` in progress.
+    pub fn cancel(&self, challenge: &[u8; 32]) -> eyre::Result<()> {
+        log::debug!(
+            "cancelling scans for challenge {challenge:X?} on {}",
+            self.address
+        );
+        let request = CancelRequest {
+            challenge: *challenge,
+        };
+        let url = format!("{}/cancel", self.address);
+        send(&url, self.client.post(&url).json(&request))?;
+        Ok(())
+    }
+}
+
+/// Client of the coordinator.
+#[derive(Debug, Clone)]
+pub struct RemoteCoordinator {
+    address: String,
+    client: reqwest::blocking::Client,
+}
+
+impl RemoteCoordinator {
+    /// Create a client of the coordinator available on `address` (i.e. "http://localhost:3200").
+    pub fn new(address: &str) -> eyre::Result<Self> {
+        // Proving might take a long time.
+        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+        Ok(Self {
+            address: address.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    /// Request a proof for `challenge`.
+    ///
+    /// Once `stop` is set, proving is cancelled on the coordinator
+    /// and it returns without waiting for its response.
+    pub fn prove(&self, challenge: &[u8; 32], stop: &AtomicBool) -> eyre::Result<Proof<'static>> {
+        let url = format!("{}/prove", self.address);
+        log::debug!("requesting proof for challenge {challenge:X?} from {url}");
+        let request = ProveRequest {
+            challenge: *challenge,
+        };
+        let (tx, rx) = mpsc::channel();
+        let client = self.client.clone();
+        std::thread::spawn(move || tx.send(post(&client, &url, &request)));
+
+        loop {
+            match rx.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(result) => return result,
+                Err(mpsc::RecvTimeoutError::Timeout) if stop.load(Ordering::Relaxed) => {
+                    self.cancel(challenge)?;
+                    eyre::bail!("proof generation was stopped");
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    eyre::bail!("the request for proof was dropped")
+                }
+            }
+        }
+    }
+
+    /// Stop proving `challenge` on the coordinator.
+    pub fn cancel(&self, challenge: &[u8; 32]) -> eyre::Result<()> {
+        log::debug!(
+            "cancelling proof for challenge {challenge:X?} on {}",
+            self.address
+        );
+        let request = CancelRequest {
+            challenge: *challenge,
+        };
+        let url = format!("{}/cancel", self.address);
+        send(&url, self.client.post(&url).json(&request))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+//! Coordinator splitting proving between workers
+
+use std::{
+    ops::Range,
+    sync::{atomic::Ordering, Arc},
+};
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use axum::{routing::post, Router};
+use eyre::Context;
+use post::{
+    config::ProofConfig,
+    metadata::PostMetadata,
+    pow,
+    prove::{merge_partial_proofs, Proof, Prover8_56, ProvingParams, MAX_NONCES},
+};
+
+use crate::{client::WorkerClient, CancelRequest, InProgress, ProveRequest, ScanRequest};
+
+/// Ranges of labels are aligned to this many labels (a chunk of AES batch).
+const LABELS_ALIGNMENT: u64 = 8;
+
+pub struct Coordinator {
+    metadata: PostMetadata,
+    cfg: ProofConfig,
+    nonces: u32,
+    workers: Vec<String>,
+    pow_prover: Box<dyn pow::Prover + Send + Sync>,
+    proofs: InProgress,
+}
+
+impl Coordinator {
+    /// Create a coordinator proving the POS data described by `metadata` with `nonces` nonces
+    /// per pass, split between the `workers` (addresses, i.e. "http://10.0.0.2:3100").
+    /// The `nonces` must be a multiple of 16 dividing [MAX_NONCES].
+    ///
+    /// The k2pows are calculated with `pow_prover`.
+    pub fn new(
+        metadata: PostMetadata,
+        cfg: ProofConfig,
+        nonces: usize,
+        workers: Vec<String>,
+        pow_prover: Box<dyn pow::Prover + Send + Sync>,
+    ) -> eyre::Result<Self> {
+        eyre::ensure!(!workers.is_empty(), "at least one worker is required");
+        let per_aes = Prover8_56::NONCES_PER_AES as usize;
+        eyre::ensure!(
+            nonces > 0 && nonces % per_aes == 0,
+            "nonces must be a positive multiple of {per_aes}"
+        );
+        // Every pass must hold the same number of nonce groups
+        eyre::ensure!(
+            MAX_NONCES as usize % nonces == 0,
+            "nonces must divide {MAX_NONCES}"
+        );
+        Ok(Self {
+            metadata,
+            cfg,
+            nonces: nonces as u32,
+            workers,
+            pow_prover,
+            proofs: InProgress::default(),
+        })
+    }
+
+    /// Check that all workers hold the POS data the coordinator proves.
+    pub fn check_workers(&self) -> eyre::Result<()> {
+        for (address, worker) in self.workers.iter().zip(self.worker_clients()?) {
+            let metadata = worker
+                .metadata()
+                .wrap_err_with(|| format!("getting metadata of worker {address}"))?;
+            eyre::ensure!(
+                metadata.node_id == self.metadata.node_id,
+                "worker {address} holds POS data of node {:X?}, expected {:X?}",
+                metadata.node_id,
+                self.metadata.node_id
+            );
+            eyre::ensure!(
+                metadata.total_labels() == self.metadata.total_labels(),
+                "worker {address} holds {} labels, expected {}",
+                metadata.total_labels(),
+                self.metadata.total_labels()
+            );
+        }
+        Ok(())
+    }
+
+    /// Generate a proof for the challenge.
+    ///
+    /// Every pass over the POS data is split into a range of labels for each worker.
+    /// If none of the nonces of a pass collects enough labels, the next pass tries the following nonces.
+    /// If any worker fails, the scans of the others are cancelled. Proving can be stopped
+    /// with [Coordinator::cancel].
+    pub fn prove(&self, challenge: &[u8; 32]) -> eyre::Result<Proof<'static>> {
+        let running = self.proofs.start(challenge);
+        let params = ProvingParams::new(&self.metadata, &self.cfg)?;
+        let total_labels = self.metadata.total_labels();
+        let ranges = split_labels(total_labels, self.workers.len());
+        let workers = self.worker_clients()?;
+        let workers = &workers;
+
+        for start in (0..MAX_NONCES).step_by(self.nonces as usize) {
+            if running.stop.load(Ordering::Relaxed) {
+                eyre::bail!("proof generation was stopped");
+            }
+            let nonces = start..start + self.nonces;
+            log::info!(
+                "proving with nonces {nonces:?} on {} workers",
+                workers.len()
+            );
+            let pows = self.pows(challenge, nonces.clone(), &params)?;
+            let request = ScanRequest {
+                challenge: *challenge,
+                nonces: nonces.clone(),
+                pows: pows.clone(),
+                difficulty: params.difficulty,
+                pow_difficulty: params.pow_difficulty,
+                labels: 0..0,
+                k2: self.cfg.k2,
+            };
+
+            let partials = std::thread::scope(|s| {
+                let handles = workers
+                    .iter()
+                    .zip(&ranges)
+                    .map(|(worker, labels)| {
+                        let request = ScanRequest {
+                            labels: labels.clone(),
+                            ..request.clone()
+                        };
+                        s.spawn(move || {
+                            let result = worker.scan(&request);
+                            if result.is_err() {
+                                // The pass is lost, stop the other workers.
+                                cancel_scans(workers, challenge);
+                            }
+                            result
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect::<eyre::Result<Vec<_>>>()
+            });
+            if running.stop.load(Ordering::Relaxed) {
+                eyre::bail!("proof generation was stopped");
+            }
+            let partials = partials?;
+            for (partial, labels) in partials.iter().zip(&ranges) {
+                // The worker clips the range to the POS data it holds.
+                eyre::ensure!(
+                    partial.labels == *labels,
+                    "a worker scanned labels {:?} instead of {labels:?}",
+                    partial.labels
+                );
+            }
+
+            let prover = crate::prover(challenge, nonces, &pows, params, &self.metadata.node_id)?;
+            if let Some(proof) = merge_partial_proofs(&partials, &prover, self.cfg.k2, total_labels)
+            {
+                log::info!("found proof for nonce: {}", proof.nonce);
+                return Ok(proof);
+            }
+        }
+        eyre::bail!("no proof found with any of {MAX_NONCES} nonces")
+    }
+
+    /// Stop proving `challenge`, cancelling the scans of the workers.
+    pub fn cancel(&self, challenge: &[u8; 32]) {
+        let cancelled = self.proofs.cancel(challenge);
+        log::info!("cancelled {cancelled} proofs for challenge {challenge:X?}");
+        match self.worker_clients() {
+            Ok(workers) => cancel_scans(&workers, challenge),
+            Err(e) => log::warn!("failed to cancel scans: {e:#}"),
+        }
+    }
+
+    // The clients are blocking, so they are created when needed (outside of the async runtime).
+    fn worker_clients(&self) -> eyre::Result<Vec<WorkerClient>> {
+        self.workers
+            .iter()
+            .map(|address| WorkerClient::new(address))
+            .collect()
+    }
+
+    /// Calculate the k2pows of the nonce groups of `nonces`.
+    fn pows(
+        &self,
+        challenge: &[u8; 32],
+        nonces: Range<u32>,
+        params: &ProvingParams,
+    ) -> eyre::Result<Vec<u64>> {
+        let per_aes = Prover8_56::NONCES_PER_AES;
+        (nonces.start / per_aes..nonces.end / per_aes)
+            .map(|group| {
+                log::debug!("calculating proof of work for nonce group {group}");
+                let pow = self.pow_prover.prove(
+                    group.try_into()?,
+                    challenge[..8].try_into().unwrap(),
+                    &params.pow_difficulty,
+                    &self.metadata.node_id,
+                )?;
+                Ok(pow)
+            })
+            .collect()
+    }
+}
+
+/// Cancel the scans for `challenge` on all `workers`.
+///
+/// It's best effort, the failures are only logged.
+fn cancel_scans(workers: &[WorkerClient], challenge: &[u8; 32]) {
+    for worker in workers {
+        if let Err(e) = worker.cancel(challenge) {
+            log::warn!("failed to cancel scans: {e:#}");
+        }
+    }
+}
+
+/// Split `total` labels into (at most) `parts` aligned ranges of similar size.
+fn split_labels(total: u64, parts: usize) -> Vec<Range<u64>> {
+    let size = total
+        .div_ceil(parts as u64)
+        .next_multiple_of(LABELS_ALIGNMENT);
+    (0..total)
+        .step_by(size.max(1) as usize)
+        .map(|start| start..(start + size).min(total))
+        .collect()
+}
+
+async fn prove(
+    State(coordinator): State<Arc<Coordinator>>,
+    Json(request): Json<ProveRequest>,
+) -> Result<Json<Proof<'static>>, (StatusCode, String)> {
+    log::info!("generating proof for challenge {:X?}", request.challenge);
+
+    let result = tokio::task::spawn_blocking(move || coordinator.prove(&request.challenge))
+        .await
+        .map_err(|e| {
+            log::error!("internal error generating proof: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "error generating proof".into(),
+            )
+        })?;
+
+    match result {
+        Ok(proof) => Ok(Json(proof)),
+        Err(e) => {
+            log::error!("failed to generate proof: {e:?}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))
+        }
+    }
+}
+
+async fn cancel(
+    State(coordinator): State<Arc<Coordinator>>,
+    Json(request): Json<CancelRequest>,
+) -> Result<(), (StatusCode, String)> {
+    log::info!("cancelling proof for challenge {:X?}", request.challenge);
+
+    // Cancelling is forwarded to the workers with a blocking client.
+    tokio::task::spawn_blocking(move || coordinator.cancel(&request.challenge))
+        .await
+        .map_err(|e| {
+            log::error!("internal error cancelling proof: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "error cancelling proof".into(),
+            )
+        })
+}
+
+/// Create the router of the coordinator.
+pub fn router(coordinator: Arc<Coordinator>) -> Router {
+    Router::new()
+        .route("/prove", post(prove))
+        .route("/cancel", post(cancel))
+        .with_state(coordinator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creating_coordinator() {
+        let create = |nonces| {
+            Coordinator::new(
+                PostMetadata::default(),
+                ProofConfig {
+                    k1: 26,
+                    k2: 37,
+                    k3: 37,
+                    pow_difficulty: [0xFF; 32],
+                },
+                nonces,
+                vec!["http://localhost:3100".into()],
+                Box::new(pow::MockProver::new()),
+            )
+        };
+        assert!(create(16).is_ok());
+        assert!(create(128).is_ok());
+        assert!(create(MAX_NONCES as usize).is_ok());
+        assert!(create(0).is_err());
+        assert!(create(24).is_err());
+        // doesn't divide the nonces
+        assert!(create(160).is_err());
+        assert!(create(MAX_NONCES as usize * 2).is_err());
+    }
+
+    #[test]
+    fn splitting_labels() {
+        assert_eq!(vec![0..8, 8..16], split_labels(16, 2));
+        assert_eq!(vec![0..16, 16..30], split_labels(30, 2));
+        assert_eq!(vec![0..8, 8..16, 16..17], split_labels(17, 3));
+        // less ranges than parts if there is not enough labels
+        assert_eq!(vec![0..5], split_labels(5, 3));
+        assert!(split_labels(0, 3).is_empty());
+    }
+}
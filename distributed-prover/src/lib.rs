@@ -0,0 +1,164 @@
+//! Distributed proving
+//!
+//! Proving is a scan of the POS data collecting, for every nonce, the indices of labels
+//! satisfying the difficulty. The scan can be split between several machines having access
+//! to the POS data:
+//! - the [worker]s scan the ranges of labels they are given and return the indices found,
+//! - the [coordinator] calculates the k2pows, hands the ranges out to the workers
+//!   and merges the indices into a proof.
+//!
+//! They talk over HTTP with JSON messages. Proving of a challenge is cancelled
+//! with a [CancelRequest], the coordinator forwards it to the workers.
+
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use post::{
+    pow,
+    prove::{Prover8_56, ProvingParams},
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+pub mod client;
+pub mod coordinator;
+pub mod worker;
+
+/// Request to scan a range of labels, sent by the coordinator to a worker.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScanRequest {
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+    pub nonces: Range<u32>,
+    /// k2pow of every group of 16 nonces in `nonces`
+    pub pows: Vec<u64>,
+    pub difficulty: u64,
+    #[serde_as(as = "Hex")]
+    pub pow_difficulty: [u8; 32],
+    pub labels: Range<u64>,
+    pub k2: u32,
+}
+
+/// Request to generate a proof, sent to the coordinator.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProveRequest {
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+}
+
+/// Request to cancel proving of a challenge, sent to the coordinator and by it to the workers.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CancelRequest {
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+}
+
+/// Stop flags of the requests in progress, by challenge.
+#[derive(Debug, Default)]
+struct InProgress(Mutex<Vec<([u8; 32], Arc<AtomicBool>)>>);
+
+impl InProgress {
+    /// Register a request for `challenge`. It's stopped by [InProgress::cancel]
+    /// until the returned guard is dropped.
+    fn start(&self, challenge: &[u8; 32]) -> Running<'_> {
+        let stop = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().push((*challenge, stop.clone()));
+        Running {
+            in_progress: self,
+            stop,
+        }
+    }
+
+    /// Stop all requests for `challenge`, returning how many were stopped.
+    fn cancel(&self, challenge: &[u8; 32]) -> usize {
+        let requests = self.0.lock().unwrap();
+        requests
+            .iter()
+            .filter(|(ch, _)| ch == challenge)
+            .inspect(|(_, stop)| stop.store(true, Ordering::Relaxed))
+            .count()
+    }
+}
+
+/// A request in progress, see [InProgress::start].
+struct Running<'a> {
+    in_progress: &'a InProgress,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut requests = self.in_progress.0.lock().unwrap();
+        requests.retain(|(_, stop)| !Arc::ptr_eq(stop, &self.stop));
+    }
+}
+
+/// PoW prover returning k2pows calculated beforehand (by the coordinator).
+struct KnownPoWs(BTreeMap<u8, u64>);
+
+impl pow::Prover for KnownPoWs {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<u64, pow::Error> {
+        self.0
+            .get(&nonce_group)
+            .copied()
+            .ok_or(pow::Error::PoWNotFound)
+    }
+}
+
+/// Create a prover for `nonces` with the k2pows of their nonce groups.
+fn prover(
+    challenge: &[u8; 32],
+    nonces: Range<u32>,
+    pows: &[u64],
+    params: ProvingParams,
+    miner_id: &[u8; 32],
+) -> eyre::Result<Prover8_56> {
+    let groups = nonces.start / Prover8_56::NONCES_PER_AES..nonces.end / Prover8_56::NONCES_PER_AES;
+    eyre::ensure!(
+        groups.len() == pows.len(),
+        "expected {} k2pows for nonces {nonces:?}, got {}",
+        groups.len(),
+        pows.len()
+    );
+    let pows = groups
+        .map(u8::try_from)
+        .zip(pows.iter().copied())
+        .map(|(group, pow)| Ok((group?, pow)))
+        .collect::<eyre::Result<_>>()?;
+    Prover8_56::new(challenge, nonces, params, &KnownPoWs(pows), miner_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_requests_in_progress() {
+        let in_progress = InProgress::default();
+        let first = in_progress.start(&[1; 32]);
+        let second = in_progress.start(&[2; 32]);
+
+        assert_eq!(1, in_progress.cancel(&[1; 32]));
+        assert!(first.stop.load(Ordering::Relaxed));
+        assert!(!second.stop.load(Ordering::Relaxed));
+
+        // finished requests are forgotten
+        drop(first);
+        assert_eq!(0, in_progress.cancel(&[1; 32]));
+    }
+}
@@ -0,0 +1,121 @@
+//! Worker scanning ranges of labels of POS data on request of the coordinator
+
+use std::{path::Path, sync::Arc};
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use eyre::Context;
+use post::{
+    layout,
+    metadata::{self, PostMetadata},
+    prove::{prove_range, PartialProof, ProvingParams},
+    reader::{PostDataSource, ReadSettings},
+};
+
+use crate::{CancelRequest, InProgress, ScanRequest};
+
+/// Worker holding the POS data.
+pub struct Worker {
+    source: Box<dyn PostDataSource>,
+    metadata: PostMetadata,
+    read: ReadSettings,
+    threads: usize,
+    scans: InProgress,
+}
+
+impl Worker {
+    /// Open the POS data in `datadir` to scan it with `threads` threads ('0' means all available).
+    pub fn open(datadir: &Path, read: ReadSettings, threads: usize) -> eyre::Result<Self> {
+        Ok(Self {
+            source: layout::open_source(datadir)?,
+            metadata: metadata::load(datadir).wrap_err("loading metadata")?,
+            read,
+            threads,
+            scans: InProgress::default(),
+        })
+    }
+
+    /// Scan the range of labels of the request.
+    ///
+    /// It can be stopped with [Worker::cancel].
+    pub fn scan(&self, request: &ScanRequest) -> eyre::Result<PartialProof> {
+        let running = self.scans.start(&request.challenge);
+        let params = ProvingParams {
+            difficulty: request.difficulty,
+            pow_difficulty: request.pow_difficulty,
+        };
+        let prover = crate::prover(
+            &request.challenge,
+            request.nonces.clone(),
+            &request.pows,
+            params,
+            &self.metadata.node_id,
+        )?;
+        prove_range(
+            self.source.as_ref(),
+            &self.metadata,
+            &prover,
+            request.labels.clone(),
+            request.k2,
+            self.read,
+            self.threads,
+            &running.stop,
+        )
+    }
+
+    /// Stop the scans for `challenge` in progress.
+    pub fn cancel(&self, challenge: &[u8; 32]) {
+        let cancelled = self.scans.cancel(challenge);
+        log::info!("cancelled {cancelled} scans for challenge {challenge:X?}");
+    }
+}
+
+async fn scan(
+    State(worker): State<Arc<Worker>>,
+    Json(request): Json<ScanRequest>,
+) -> Result<Json<PartialProof>, (StatusCode, String)> {
+    log::info!(
+        "scanning labels {:?} with nonces {:?}",
+        request.labels,
+        request.nonces
+    );
+
+    let result = tokio::task::spawn_blocking(move || worker.scan(&request))
+        .await
+        .map_err(|e| {
+            log::error!("internal error scanning labels: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "error scanning labels".into(),
+            )
+        })?;
+
+    match result {
+        Ok(partial) => Ok(Json(partial)),
+        Err(e) => {
+            log::error!("failed to scan labels: {e:?}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))
+        }
+    }
+}
+
+async fn get_metadata(State(worker): State<Arc<Worker>>) -> Json<PostMetadata> {
+    Json(worker.metadata)
+}
+
+async fn cancel(State(worker): State<Arc<Worker>>, Json(request): Json<CancelRequest>) {
+    worker.cancel(&request.challenge);
+}
+
+/// Create the router of the worker.
+pub fn router(worker: Arc<Worker>) -> Router {
+    Router::new()
+        .route("/scan", post(scan))
+        .route("/cancel", post(cancel))
+        .route("/metadata", get(get_metadata))
+        .with_state(worker)
+}
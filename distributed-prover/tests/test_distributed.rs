@@ -0,0 +1,183 @@
+use std::{
+    future::IntoFuture,
+    net::SocketAddr,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+use axum::Router;
+use distributed_prover::{
+    client::RemoteCoordinator,
+    coordinator::{self, Coordinator},
+    worker::{self, Worker},
+};
+use post::{
+    metadata::{PostMetadata, ProofMetadata},
+    pow::{
+        randomx::{PoW, RandomXFlag},
+        MockProver,
+    },
+    reader::ReadSettings,
+    verification::Verifier,
+};
+use tokio::net::TcpListener;
+
+use common::{init_post, CHALLENGE};
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+async fn start_server(app: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, app.into_make_service()).into_future());
+    addr
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_distributed_proving() {
+    let datadir = tempfile::tempdir().unwrap();
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    let mut workers = Vec::new();
+    for _ in 0..3 {
+        let worker = Worker::open(datadir.path(), ReadSettings::default(), 1).unwrap();
+        let addr = start_server(worker::router(Arc::new(worker))).await;
+        workers.push(format!("http://{addr}"));
+    }
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let coordinator = Coordinator::new(
+        metadata,
+        cfg,
+        32,
+        workers,
+        Box::new(PoW::new(pow_flags).unwrap()),
+    )
+    .unwrap();
+    let coordinator = tokio::task::spawn_blocking(move || {
+        coordinator.check_workers().unwrap();
+        coordinator
+    })
+    .await
+    .unwrap();
+    let addr = start_server(coordinator::router(Arc::new(coordinator))).await;
+
+    let remote = RemoteCoordinator::new(&format!("http://{addr}")).unwrap();
+    let proof =
+        tokio::task::spawn_blocking(move || remote.prove(CHALLENGE, &AtomicBool::new(false)))
+            .await
+            .unwrap()
+            .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *CHALLENGE);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_distributed_proving_worker_failure() {
+    let datadir = tempfile::tempdir().unwrap();
+    let (metadata, cfg, _) = init_post(datadir.path());
+
+    // No worker listens on it
+    let worker = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut pow_prover = MockProver::new();
+    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+    let coordinator = Coordinator::new(
+        metadata,
+        cfg,
+        16,
+        vec![format!("http://{worker}")],
+        Box::new(pow_prover),
+    )
+    .unwrap();
+    let addr = start_server(coordinator::router(Arc::new(coordinator))).await;
+
+    let remote = RemoteCoordinator::new(&format!("http://{addr}")).unwrap();
+    let result =
+        tokio::task::spawn_blocking(move || remote.prove(&[0; 32], &AtomicBool::new(false)))
+            .await
+            .unwrap();
+    assert!(result.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_distributed_proving_different_pos_data() {
+    let datadir = tempfile::tempdir().unwrap();
+    let (metadata, cfg, _) = init_post(datadir.path());
+
+    let worker = Worker::open(datadir.path(), ReadSettings::default(), 1).unwrap();
+    let worker = start_server(worker::router(Arc::new(worker))).await;
+
+    let mut pow_prover = MockProver::new();
+    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+    // The coordinator expects more labels than the worker holds
+    let metadata = PostMetadata {
+        num_units: metadata.num_units + 1,
+        ..metadata
+    };
+    let coordinator = Coordinator::new(
+        metadata,
+        cfg,
+        16,
+        vec![format!("http://{worker}")],
+        Box::new(pow_prover),
+    )
+    .unwrap();
+
+    let (coordinator, checked) = tokio::task::spawn_blocking(move || {
+        let checked = coordinator.check_workers();
+        (coordinator, checked)
+    })
+    .await
+    .unwrap();
+    assert!(checked.unwrap_err().to_string().contains("labels"));
+
+    // Proving doesn't silently skip the labels missing on the worker
+    let result = tokio::task::spawn_blocking(move || coordinator.prove(CHALLENGE))
+        .await
+        .unwrap();
+    assert!(result.unwrap_err().to_string().contains("instead of"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_distributed_proving_stopped() {
+    let datadir = tempfile::tempdir().unwrap();
+    let (metadata, cfg, _) = init_post(datadir.path());
+
+    let worker = Worker::open(datadir.path(), ReadSettings::default(), 1).unwrap();
+    let worker = start_server(worker::router(Arc::new(worker))).await;
+
+    let mut pow_prover = MockProver::new();
+    pow_prover.expect_prove().returning(|_, _, _, _| {
+        std::thread::sleep(Duration::from_millis(500));
+        Ok(0)
+    });
+    let coordinator = Coordinator::new(
+        metadata,
+        cfg,
+        16,
+        vec![format!("http://{worker}")],
+        Box::new(pow_prover),
+    )
+    .unwrap();
+    let addr = start_server(coordinator::router(Arc::new(coordinator))).await;
+
+    let remote = RemoteCoordinator::new(&format!("http://{addr}")).unwrap();
+    let start = Instant::now();
+    let result =
+        tokio::task::spawn_blocking(move || remote.prove(CHALLENGE, &AtomicBool::new(true)))
+            .await
+            .unwrap();
+    assert!(result.unwrap_err().to_string().contains("stopped"));
+    // It doesn't wait for the k2pow
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
@@ -232,13 +232,11 @@ pub extern "C" fn verify_pos(
         from_file,
         to_file,
         scrypt,
+        None,
     ) {
         Ok(_) => VerifyResult::Ok,
-        Err(VerificationError::InvalidLabel { idx, offset }) => {
-            log::info!(
-                "POS data is invalid: {}",
-                VerificationError::InvalidLabel { idx, offset }
-            );
+        Err(e @ VerificationError::InvalidLabels(_)) => {
+            log::info!("POS data is invalid: {e}");
             VerifyResult::Invalid
         }
         Err(e) => {
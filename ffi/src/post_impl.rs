@@ -12,7 +12,7 @@ use post::{
     config::{InitConfig, ProofConfig},
     metadata::ProofMetadata,
     pow::randomx::{PoW, RandomXFlag},
-    prove,
+    prove::{self, Nonces, ProvingOptions},
     verification::Verifier,
 };
 
@@ -105,8 +105,20 @@ fn _generate_proof(
     let challenge = unsafe { std::slice::from_raw_parts(challenge, 32) };
     let challenge = challenge.try_into()?;
 
+    let pow_prover = PoW::new(pow_flags)?;
     let stop = AtomicBool::new(false);
-    let proof = prove::generate_proof(datadir, challenge, cfg, nonces, threads, pow_flags, stop)?;
+    let proof = prove::generate_proof(
+        datadir,
+        challenge,
+        cfg,
+        &pow_prover,
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(nonces),
+            threads,
+            ..Default::default()
+        },
+    )?;
     Ok(Box::new(Proof::from(proof)))
 }
 
@@ -133,17 +145,31 @@ pub extern "C" fn recommended_pow_flags() -> RandomXFlag {
     RandomXFlag::get_recommended_flags()
 }
 
+/// Create a new verifier
+///
+/// `threads` is the number of threads to regenerate K3 labels of a proof with
+/// ('0' means all available threads).
 #[no_mangle]
-pub extern "C" fn new_verifier(flags: RandomXFlag, out: *mut *mut Verifier) -> VerifyResult {
+pub extern "C" fn new_verifier(
+    flags: RandomXFlag,
+    threads: usize,
+    out: *mut *mut Verifier,
+) -> VerifyResult {
     if out.is_null() {
         return VerifyResult::InvalidArgument;
     }
-    match PoW::new(flags) {
+    let pow_verifier = match PoW::new(flags) {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            log::error!("{e:?}");
+            return VerifyResult::FailedToCreateVerifier;
+        }
+    };
+    match Verifier::new(Box::new(pow_verifier), threads) {
         Ok(verifier) => {
-            unsafe { *out = Box::into_raw(Box::new(Verifier::new(Box::new(verifier)))) };
+            unsafe { *out = Box::into_raw(Box::new(verifier)) };
             VerifyResult::Ok
         }
-
         Err(e) => {
             log::error!("{e:?}");
             VerifyResult::FailedToCreateVerifier
@@ -231,7 +257,7 @@ mod tests {
     #[test]
     fn create_and_free_verifier() {
         let mut verifier = std::ptr::null_mut();
-        let result = super::new_verifier(RandomXFlag::default(), &mut verifier);
+        let result = super::new_verifier(RandomXFlag::default(), 1, &mut verifier);
         assert_eq!(result, super::VerifyResult::Ok);
         assert!(!verifier.is_null());
         super::free_verifier(verifier);
@@ -304,7 +330,8 @@ mod tests {
 
         // Create verifier
         let mut verifier = std::ptr::null_mut();
-        let result: crate::post_impl::VerifyResult = super::new_verifier(pow_flags, &mut verifier);
+        let result: crate::post_impl::VerifyResult =
+            super::new_verifier(pow_flags, 0, &mut verifier);
         assert_eq!(result, super::VerifyResult::Ok);
         assert!(!verifier.is_null());
 
@@ -7,10 +7,13 @@ use std::{
 
 use base64::{engine::general_purpose, Engine};
 use clap::{Args, Parser, Subcommand, ValueEnum};
+use cli_args::SamplingArgs;
 use eyre::Context;
 use post::{
     config::ScryptParams,
     initialize::{CpuInitializer, Initialize, LABEL_SIZE},
+    layout::{self, Layout},
+    repair::DamagedLabels,
 };
 use rand::seq::IteratorRandom;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
@@ -33,6 +36,14 @@ enum Commands {
     Initialize(InitializeArgs),
     ListProviders,
     VerifyData(VerifyData),
+    /// Extend initialized POS data with more units
+    Extend(ResizeArgs),
+    /// Shrink initialized POS data to fewer units
+    Shrink(ResizeArgs),
+    /// Rewrite initialized POS data into files of a different size
+    Reshard(ReshardArgs),
+    /// Regenerate corrupted labels of initialized POS data in place
+    Repair(RepairArgs),
 }
 
 #[derive(Args)]
@@ -65,6 +76,46 @@ struct InitializeArgs {
     #[arg(long, default_value = "./post-data")]
     output: PathBuf,
 
+    /// Store all labels contiguously in this single file or raw block device
+    /// instead of `postdata_N.bin` files in the output directory
+    /// (which still holds the metadata).
+    #[arg(long)]
+    single_file: Option<PathBuf>,
+
+    /// Offset (in bytes) of the labels in the single file
+    #[arg(long, default_value_t = 0, requires = "single_file")]
+    offset: u64,
+
+    /// Spread `postdata_N.bin` files evenly over these directories (i.e. on separate disks)
+    /// instead of the output directory (which still holds the metadata).
+    /// Can be repeated.
+    #[arg(long, conflicts_with = "single_file")]
+    stripe: Vec<PathBuf>,
+
+    /// Provider ID to use for GPU initialization.
+    /// Use `initializer list-providers` to list available providers.
+    /// If not specified, the first available provider will be used.
+    #[arg(long)]
+    provider: Option<u32>,
+
+    #[clap(value_enum, default_value_t = InitializationMethod::Gpu)]
+    method: InitializationMethod,
+}
+
+#[derive(Args)]
+struct ResizeArgs {
+    /// Scrypt N parameter
+    #[arg(short, long, default_value_t = 8192)]
+    n: usize,
+
+    /// Total number of units after resizing
+    #[arg(short, long)]
+    units: u32,
+
+    /// Path to initialized POS data
+    #[arg(long, default_value = "./post-data")]
+    output: PathBuf,
+
     /// Provider ID to use for GPU initialization.
     /// Use `initializer list-providers` to list available providers.
     /// If not specified, the first available provider will be used.
@@ -75,6 +126,66 @@ struct InitializeArgs {
     method: InitializationMethod,
 }
 
+#[derive(Args)]
+struct ReshardArgs {
+    /// Scrypt N parameter
+    #[arg(short, long, default_value_t = 8192)]
+    n: usize,
+
+    /// New max size of single file
+    #[arg(short, long)]
+    max_file_size: u64,
+
+    /// Path to initialized POS data
+    #[arg(long, default_value = "./post-data")]
+    output: PathBuf,
+
+    /// Fraction of re-sharded data (in %) to verify before replacing the original files
+    #[arg(short, long, default_value_t = 0.1)]
+    fraction: f64,
+}
+
+#[derive(Args)]
+struct RepairArgs {
+    /// Scrypt N parameter
+    #[arg(short, long, default_value_t = 8192)]
+    n: usize,
+
+    /// Path to initialized POS data
+    #[arg(long, default_value = "./post-data")]
+    output: PathBuf,
+
+    /// Index of a POS data file to regenerate entirely (can be repeated)
+    #[arg(long)]
+    file: Vec<usize>,
+
+    /// Corrupted label to regenerate, given as `<file index>:<offset in bytes>`
+    /// as reported by POS data verification (can be repeated)
+    #[arg(long, value_parser(parse_label_offset))]
+    offset: Vec<(usize, u64)>,
+
+    /// Fraction of repaired labels (in %) to verify afterwards.
+    /// Consider lowering it when regenerating entire files.
+    #[arg(short, long, default_value_t = 100.0)]
+    fraction: f64,
+
+    /// Provider ID to use for GPU initialization.
+    /// Use `initializer list-providers` to list available providers.
+    /// If not specified, the first available provider will be used.
+    #[arg(long)]
+    provider: Option<u32>,
+
+    #[clap(value_enum, default_value_t = InitializationMethod::Gpu)]
+    method: InitializationMethod,
+}
+
+fn parse_label_offset(arg: &str) -> eyre::Result<(usize, u64)> {
+    let (file, offset) = arg
+        .split_once(':')
+        .ok_or_else(|| eyre::eyre!("expected <file index>:<offset>"))?;
+    Ok((file.parse()?, offset.parse()?))
+}
+
 #[derive(Clone, ValueEnum)]
 enum InitializationMethod {
     Cpu,
@@ -101,6 +212,8 @@ struct VerifyData {
     /// Base64-encoded commitment ATX ID
     #[arg(long, default_value = "ZuxocVjIYWfv7A/K1Lmm8+mNsHzAZaWVpbl5+KINx+I=")]
     commitment_atx_id: String,
+    #[command(flatten)]
+    sampling: SamplingArgs,
 }
 
 fn calc_commitment(node_id: &str, commitment_atx_id: &str) -> eyre::Result<[u8; 32]> {
@@ -130,7 +243,9 @@ fn verify_data(args: VerifyData) -> eyre::Result<()> {
     let labels_to_verify = (labels_in_file as f64 * (args.fraction / 100.0)) as usize;
     let scrypt_params = ScryptParams::new(args.n, 1, 1);
 
-    let mut rng = rand::thread_rng();
+    let seed = args.sampling.seed();
+    println!("Verifying with sampling seed: {seed}");
+    let mut rng = seed.rng(args.first_label_index);
     (0..labels_in_file)
         .choose_multiple(&mut rng, labels_to_verify)
         .into_iter()
@@ -167,21 +282,52 @@ fn verify_data(args: VerifyData) -> eyre::Result<()> {
     Ok(())
 }
 
-fn initialize(args: InitializeArgs) -> eyre::Result<()> {
-    eyre::ensure!(args.n.is_power_of_two(), "scrypt N must be a power of two");
+fn new_initializer(
+    method: InitializationMethod,
+    n: usize,
+    provider: Option<u32>,
+) -> eyre::Result<Box<dyn Initialize>> {
+    eyre::ensure!(n.is_power_of_two(), "scrypt N must be a power of two");
 
-    let mut initializer: Box<dyn Initialize> = match args.method {
-        InitializationMethod::Cpu => Box::new(CpuInitializer::new(ScryptParams::new(args.n, 1, 1))),
+    Ok(match method {
+        InitializationMethod::Cpu => Box::new(CpuInitializer::new(ScryptParams::new(n, 1, 1))),
         InitializationMethod::Gpu => Box::new(OpenClInitializer::new(
-            args.provider.map(ProviderId),
-            args.n,
+            provider.map(ProviderId),
+            n,
             Some(DeviceType::GPU | DeviceType::CPU),
         )?),
-    };
+    })
+}
+
+fn initialize(args: InitializeArgs) -> eyre::Result<()> {
+    let mut initializer = new_initializer(args.method, args.n, args.provider)?;
 
     let node_id = general_purpose::STANDARD.decode(args.node_id)?;
     let commitment_atx_id = general_purpose::STANDARD.decode(args.commitment_atx_id)?;
 
+    let labels_per_file = (args.max_file_size / LABEL_SIZE) as u64;
+    let layout = match args.single_file {
+        Some(path) => Some(Layout::SingleFile {
+            path,
+            offset: args.offset,
+        }),
+        None if !args.stripe.is_empty() => {
+            let total_labels = args.labels_per_unit as u64 * args.units as u64;
+            let num_files = total_labels.div_ceil(labels_per_file) as usize;
+            Some(Layout::striped(args.stripe, num_files))
+        }
+        None => None,
+    };
+    if let Some(layout) = layout {
+        let existing = layout::load(&args.output)?;
+        eyre::ensure!(
+            existing == layout || post::metadata::load(&args.output).is_err(),
+            "POS data in {} is already stored in a different layout: {existing:?}",
+            args.output.display()
+        );
+        layout::save(&args.output, &layout)?;
+    }
+
     let now = time::Instant::now();
     let metadata = initializer
         .initialize(
@@ -190,7 +336,7 @@ fn initialize(args: InitializeArgs) -> eyre::Result<()> {
             commitment_atx_id.as_slice().try_into()?,
             args.labels_per_unit as u64,
             args.units as u32,
-            (args.max_file_size / LABEL_SIZE) as u64,
+            labels_per_file,
             Some([0xFFu8; 32]),
         )
         .map_err(|e| eyre::eyre!("initializing: {}", e))?;
@@ -207,6 +353,85 @@ fn initialize(args: InitializeArgs) -> eyre::Result<()> {
     Ok(())
 }
 
+fn extend(args: ResizeArgs) -> eyre::Result<()> {
+    let mut initializer = new_initializer(args.method, args.n, args.provider)?;
+
+    let now = time::Instant::now();
+    let metadata = initializer
+        .extend(&args.output, args.units, Some([0xFFu8; 32]))
+        .map_err(|e| eyre::eyre!("extending: {}", e))?;
+
+    println!(
+        "Extending to {} units took {:.2} seconds, vrf_nonce: {:?}",
+        metadata.num_units,
+        now.elapsed().as_secs_f64(),
+        metadata.nonce,
+    );
+    Ok(())
+}
+
+fn shrink(args: ResizeArgs) -> eyre::Result<()> {
+    // The initializer is only used if a new VRF nonce must be found
+    let mut initializer = new_initializer(args.method, args.n, args.provider)?;
+
+    let metadata = initializer
+        .shrink(&args.output, args.units, Some([0xFFu8; 32]))
+        .map_err(|e| eyre::eyre!("shrinking: {}", e))?;
+
+    println!(
+        "Shrunk to {} units, vrf_nonce: {:?}",
+        metadata.num_units, metadata.nonce,
+    );
+    Ok(())
+}
+
+fn reshard(args: ReshardArgs) -> eyre::Result<()> {
+    let now = time::Instant::now();
+    let metadata = post::reshard::reshard(
+        &args.output,
+        args.max_file_size,
+        ScryptParams::new(args.n, 1, 1),
+        args.fraction,
+    )?;
+    println!(
+        "Re-sharding to {} files took {:.2} seconds",
+        metadata.num_files(),
+        now.elapsed().as_secs_f64(),
+    );
+    Ok(())
+}
+
+fn repair(args: RepairArgs) -> eyre::Result<()> {
+    let metadata = post::metadata::load(&args.output).wrap_err("loading metadata")?;
+    let damaged = args
+        .file
+        .iter()
+        .map(|&file| DamagedLabels::whole_file(&metadata, file))
+        .chain(
+            args.offset
+                .iter()
+                .map(|&(file, offset)| DamagedLabels::at_offset(file, offset)),
+        )
+        .collect::<Vec<_>>();
+    eyre::ensure!(!damaged.is_empty(), "nothing to repair");
+
+    let mut initializer = new_initializer(args.method, args.n, args.provider)?;
+    let now = time::Instant::now();
+    post::repair::repair(
+        &args.output,
+        initializer.as_mut(),
+        &damaged,
+        ScryptParams::new(args.n, 1, 1),
+        args.fraction,
+    )?;
+    println!(
+        "Repairing {} label ranges took {:.2} seconds",
+        damaged.len(),
+        now.elapsed().as_secs_f64(),
+    );
+    Ok(())
+}
+
 fn list_providers() -> eyre::Result<()> {
     let providers = scrypt_ocl::get_providers(Some(DeviceType::GPU | DeviceType::CPU))?;
     for (id, provider) in providers.iter().enumerate() {
@@ -227,6 +452,10 @@ fn main() -> eyre::Result<()> {
         Commands::Initialize(args) => initialize(args)?,
         Commands::ListProviders => list_providers()?,
         Commands::VerifyData(v) => verify_data(v)?,
+        Commands::Extend(args) => extend(args)?,
+        Commands::Shrink(args) => shrink(args)?,
+        Commands::Reshard(args) => reshard(args)?,
+        Commands::Repair(args) => repair(args)?,
     }
 
     Ok(())
@@ -0,0 +1,54 @@
+//! Client of the k2pow service
+
+use post::pow::{Error, Prover};
+
+use crate::{ProveRequest, ProveResponse};
+
+/// PoW prover delegating the k2pow calculation to the k2pow service.
+#[derive(Debug, Clone)]
+pub struct RemoteProver {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl RemoteProver {
+    /// Create a prover using the k2pow service available on `address` (i.e. "http://localhost:3000").
+    pub fn new(address: &str) -> eyre::Result<Self> {
+        // Calculating k2pow might take a long time.
+        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+        Ok(Self {
+            url: format!("{}/prove", address.trim_end_matches('/')),
+            client,
+        })
+    }
+}
+
+impl Prover for RemoteProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, Error> {
+        let request = ProveRequest {
+            nonce_group,
+            challenge: *challenge,
+            difficulty: *difficulty,
+            miner_id: *miner_id,
+        };
+        log::debug!(
+            "requesting k2pow for nonce group {nonce_group} from {}",
+            self.url
+        );
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| Error::Internal(Box::new(e)))?;
+        let response: ProveResponse = response.json().map_err(|e| Error::Internal(Box::new(e)))?;
+        Ok(response.pow)
+    }
+}
@@ -0,0 +1,29 @@
+//! K2pow service
+//!
+//! Calculating k2pow with RandomX in the fast mode requires ~2 GiB of memory.
+//! The service allows to calculate it on a dedicated machine, shared by many
+//! POST services which use the [client::RemoteProver] instead of calculating
+//! the k2pow themselves.
+
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+pub mod client;
+pub mod server;
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProveRequest {
+    pub nonce_group: u8,
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 8],
+    #[serde_as(as = "Hex")]
+    pub difficulty: [u8; 32],
+    #[serde_as(as = "Hex")]
+    pub miner_id: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProveResponse {
+    pub pow: u64,
+}
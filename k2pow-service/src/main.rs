@@ -0,0 +1,47 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use clap::Parser;
+use cli_args::RandomXMode;
+use eyre::Context;
+use post::pow::randomx::PoW;
+use tokio::net::TcpListener;
+
+/// K2pow service
+///
+/// Calculates k2pow for POST services.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:3000")]
+    listen: SocketAddr,
+    /// number of threads to calculate k2pow with
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// modes of operation for RandomX
+    #[arg(long, default_value_t = RandomXMode::Fast)]
+    randomx_mode: RandomXMode,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Cli::parse();
+
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .wrap_err("building thread pool")?;
+
+    log::info!("initializing RandomX in {} mode", args.randomx_mode);
+    let prover = PoW::new(args.randomx_mode.into()).wrap_err("creating RandomX PoW prover")?;
+
+    log::info!("listening on: {}", args.listen);
+    let app = k2pow_service::server::new(Arc::new(prover));
+    let listener = TcpListener::bind(args.listen).await?;
+    axum::serve(listener, app.into_make_service()).await?;
+    Ok(())
+}
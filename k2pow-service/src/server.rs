@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use axum::{routing::post, Router};
+use post::pow::Prover;
+
+use crate::{ProveRequest, ProveResponse};
+
+type SharedProver = Arc<dyn Prover + Send + Sync>;
+
+async fn prove(
+    State(prover): State<SharedProver>,
+    Json(request): Json<ProveRequest>,
+) -> Result<Json<ProveResponse>, (StatusCode, String)> {
+    log::info!(
+        "calculating k2pow for nonce group {} (miner: {:X?})",
+        request.nonce_group,
+        request.miner_id
+    );
+
+    let result = tokio::task::spawn_blocking(move || {
+        prover.prove(
+            request.nonce_group,
+            &request.challenge,
+            &request.difficulty,
+            &request.miner_id,
+        )
+    })
+    .await
+    .map_err(|e| {
+        log::error!("internal error calculating k2pow: {e:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error calculating k2pow".into(),
+        )
+    })?;
+
+    match result {
+        Ok(pow) => Ok(Json(ProveResponse { pow })),
+        Err(e) => {
+            log::error!("failed to calculate k2pow: {e:?}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+    }
+}
+
+/// Create the router of the k2pow service calculating k2pow with the given prover.
+pub fn new(prover: SharedProver) -> Router {
+    Router::new()
+        .route("/prove", post(prove))
+        .with_state(prover)
+}
@@ -0,0 +1,112 @@
+use std::{future::IntoFuture, net::SocketAddr, sync::atomic::AtomicBool, sync::Arc};
+
+use k2pow_service::client::RemoteProver;
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    initialize::{CpuInitializer, Initialize},
+    metadata::ProofMetadata,
+    pow::{
+        randomx::{PoW, RandomXFlag},
+        MockProver, PowVerifier, Prover,
+    },
+    prove::{generate_proof, Nonces, ProvingOptions},
+    verification::Verifier,
+};
+use tokio::net::TcpListener;
+
+async fn start_server(prover: Arc<dyn Prover + Send + Sync>) -> SocketAddr {
+    let app = k2pow_service::server::new(prover);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, app.into_make_service()).into_future());
+    addr
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_remote_k2pow() {
+    let pow = Arc::new(PoW::new(RandomXFlag::get_recommended_flags()).unwrap());
+    let addr = start_server(pow.clone()).await;
+
+    let remote = RemoteProver::new(&format!("http://{addr}")).unwrap();
+    let difficulty = [0x0F; 32];
+    let result =
+        tokio::task::spawn_blocking(move || remote.prove(7, &[1; 8], &difficulty, &[2; 32]))
+            .await
+            .unwrap()
+            .unwrap();
+
+    pow.verify(result, 7, &[1; 8], &difficulty, &[2; 32])
+        .expect("k2pow should be valid");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_remote_k2pow_failure() {
+    let mut prover = MockProver::new();
+    prover
+        .expect_prove()
+        .returning(|_, _, _, _| Err(post::pow::Error::PoWNotFound));
+    let addr = start_server(Arc::new(prover)).await;
+
+    let remote = RemoteProver::new(&format!("http://{addr}")).unwrap();
+    let result = tokio::task::spawn_blocking(move || remote.prove(0, &[0; 8], &[0; 32], &[0; 32]))
+        .await
+        .unwrap();
+    assert!(result.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_generate_proof_with_remote_k2pow() {
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let addr = start_server(Arc::new(PoW::new(pow_flags).unwrap())).await;
+
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempfile::tempdir().unwrap();
+    let cfg = ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            31,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let remote = RemoteProver::new(&format!("http://{addr}")).unwrap();
+    let proof = tokio::task::spawn_blocking(move || {
+        generate_proof(
+            datadir.path(),
+            challenge,
+            cfg,
+            &remote,
+            AtomicBool::new(false),
+            ProvingOptions {
+                nonces: Nonces::Fixed(32),
+                threads: 1,
+                ..Default::default()
+            },
+        )
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
@@ -0,0 +1,293 @@
+//! POST command line tool
+//!
+//! Generates and verifies proofs without running the POST service.
+use std::{fs::File, ops::Range, path::PathBuf, sync::atomic::AtomicBool};
+
+use clap::{Args, Parser, Subcommand};
+use cli_args::{parse_nonces, InitConfigArgs, ProofConfigArgs, RandomXMode, SamplingArgs};
+use eyre::Context;
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    metadata::{self, ProofMetadata},
+    pos_verification,
+    pow::randomx::PoW,
+    prove::{self, Nonces, ProgressReporter, Proof, ProvingOptions},
+    reader::ReadSettings,
+    verification::Verifier,
+};
+use serde::Serialize;
+use serde_with::{hex::Hex, serde_as};
+
+/// POST command line tool
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Generate a proof for POS data and a challenge
+    Prove(ProveArgs),
+    /// Verify a proof
+    Verify(VerifyArgs),
+    /// Print the decoded metadata of POS data
+    Inspect(InspectArgs),
+    /// Check integrity of POS data and print a report (JSON) of all problems found
+    Check(CheckArgs),
+}
+
+#[derive(Args)]
+struct ProveArgs {
+    /// Directory of POS data
+    #[arg(short, long)]
+    datadir: PathBuf,
+    /// Hex-encoded challenge (32 bytes)
+    #[arg(long, value_parser(parse_hex32))]
+    challenge: [u8; 32],
+    /// Path to write the proof (JSON) to
+    #[arg(short, long, default_value = "proof.json")]
+    output: PathBuf,
+    /// Path to write the proof metadata (JSON) to,
+    /// required to verify the proof
+    #[arg(long, default_value = "proof_metadata.json")]
+    metadata_output: PathBuf,
+    #[command(flatten)]
+    cfg: ProofConfigArgs,
+    /// Number of threads to use
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+    /// Number of nonces to attempt in single pass over POS data
+    ///
+    /// Each group of 16 nonces requires a separate PoW. Must be a multiple of 16.
+    /// 'auto' means pick the largest number that keeps proving I/O-bound.
+    #[arg(long, default_value = "128", value_parser(parse_nonces))]
+    nonces: Nonces,
+    /// Modes of operation for RandomX
+    #[arg(long, default_value_t = RandomXMode::Fast)]
+    randomx_mode: RandomXMode,
+    /// Persist proof generation progress in the POS data directory
+    #[arg(long)]
+    checkpoint: bool,
+    /// Size of a single read of POS data (in bytes), must be a multiple of 128
+    #[arg(long, default_value_t = ReadSettings::default().batch_size)]
+    read_batch_size: usize,
+    /// Number of reads of POS data issued concurrently on each disk
+    #[arg(long, default_value_t = ReadSettings::default().queue_depth)]
+    read_queue_depth: usize,
+    /// Read POS data bypassing the page cache (Linux only)
+    #[arg(long)]
+    direct_io: bool,
+    /// Maximal number of passes (of `nonces` each) evaluated in a single read of POS data
+    #[arg(long, default_value_t = ProvingOptions::default().max_passes_per_read)]
+    max_passes_per_read: usize,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// Path to the proof (JSON)
+    #[arg(long, default_value = "proof.json")]
+    proof: PathBuf,
+    /// Path to the proof metadata (JSON)
+    #[arg(long, default_value = "proof_metadata.json")]
+    metadata: PathBuf,
+    #[command(flatten)]
+    cfg: ProofConfigArgs,
+    #[command(flatten)]
+    init_cfg: InitConfigArgs,
+    /// Number of threads to regenerate K3 labels with
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+    /// Check every step of verification and print a detailed report (JSON)
+    /// instead of stopping on the first failure
+    #[arg(long)]
+    report: bool,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// Directory of POS data
+    #[arg(short, long)]
+    datadir: PathBuf,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// Directory of POS data
+    #[arg(short, long)]
+    datadir: PathBuf,
+    /// Fraction of labels (in %) to verify in every file
+    #[arg(short, long, default_value_t = 1.0)]
+    fraction: f64,
+    /// Scrypt N parameter
+    #[arg(short, default_value_t = 8192)]
+    n: usize,
+    #[command(flatten)]
+    sampling: SamplingArgs,
+}
+
+fn parse_hex32(arg: &str) -> eyre::Result<[u8; 32]> {
+    hex::decode(arg)?
+        .as_slice()
+        .try_into()
+        .wrap_err("expected 32 bytes")
+}
+
+/// Logs the progress of proof generation.
+struct LogProgress;
+
+impl ProgressReporter for LogProgress {
+    fn nonces_picked(&self, nonces: usize) {
+        log::info!("using {nonces} nonces per pass");
+    }
+
+    fn new_pass(&self, pass: usize, nonces: Range<u32>) {
+        log::info!("starting pass {pass} over POS data (nonces: {nonces:?})");
+    }
+
+    fn k2pow_finished(&self, nonce_group: u32, pow: u64) {
+        log::info!("found k2pow for nonce group {nonce_group}: {pow}");
+    }
+}
+
+fn prove(args: ProveArgs) -> eyre::Result<()> {
+    let cfg = ProofConfig::from(&args.cfg);
+    let pow_prover = PoW::new(args.randomx_mode.into()).wrap_err("creating RandomX PoW prover")?;
+
+    log::info!(
+        "generating proof for challenge {}",
+        hex::encode(args.challenge)
+    );
+    let proof = prove::generate_proof(
+        &args.datadir,
+        &args.challenge,
+        cfg,
+        &pow_prover,
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: args.nonces,
+            max_passes_per_read: args.max_passes_per_read,
+            threads: args.threads,
+            read: ReadSettings {
+                batch_size: args.read_batch_size,
+                queue_depth: args.read_queue_depth,
+                direct_io: args.direct_io,
+            },
+            checkpoint: args.checkpoint,
+            reporter: &LogProgress,
+            ..Default::default()
+        },
+    )?;
+
+    let post_metadata = metadata::load(&args.datadir).wrap_err("loading metadata")?;
+    let proof_metadata = ProofMetadata::new(post_metadata, args.challenge);
+
+    serde_json::to_writer_pretty(File::create(&args.output)?, &proof)?;
+    serde_json::to_writer_pretty(File::create(&args.metadata_output)?, &proof_metadata)?;
+    println!(
+        "Proof written to {} (metadata: {})",
+        args.output.display(),
+        args.metadata_output.display()
+    );
+    Ok(())
+}
+
+fn verify(args: VerifyArgs) -> eyre::Result<()> {
+    let proof: Proof =
+        serde_json::from_reader(File::open(&args.proof).wrap_err("opening proof file")?)
+            .wrap_err("parsing proof")?;
+    let metadata: ProofMetadata =
+        serde_json::from_reader(File::open(&args.metadata).wrap_err("opening metadata file")?)
+            .wrap_err("parsing proof metadata")?;
+    let cfg = ProofConfig::from(&args.cfg);
+    let init_cfg = InitConfig::from(&args.init_cfg);
+
+    let pow_verifier = PoW::new(RandomXMode::Light.into()).wrap_err("creating RandomX verifier")?;
+    let verifier = Verifier::new(Box::new(pow_verifier), args.threads)?;
+
+    if args.report {
+        let report = verifier.verify_report(&proof, &metadata, &cfg, &init_cfg);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        eyre::ensure!(
+            report.is_valid(),
+            "proof is invalid ({} failures)",
+            report.failures.len()
+        );
+        return Ok(());
+    }
+
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .wrap_err("proof is invalid")?;
+    println!("Proof is valid");
+    Ok(())
+}
+
+/// Decoded view of the POS data metadata.
+#[serde_as]
+#[derive(Serialize)]
+struct MetadataView {
+    #[serde_as(as = "Hex")]
+    node_id: [u8; 32],
+    #[serde_as(as = "Hex")]
+    commitment_atx_id: [u8; 32],
+    #[serde_as(as = "Hex")]
+    commitment: [u8; 32],
+    labels_per_unit: u64,
+    num_units: u32,
+    total_labels: u64,
+    total_size: u64,
+    max_file_size: u64,
+    num_files: usize,
+    nonce: Option<u64>,
+    last_position: Option<u64>,
+}
+
+fn inspect(args: InspectArgs) -> eyre::Result<()> {
+    let metadata = metadata::load(&args.datadir).wrap_err("loading metadata")?;
+    let view = MetadataView {
+        node_id: metadata.node_id,
+        commitment_atx_id: metadata.commitment_atx_id,
+        commitment: post::initialize::calc_commitment(
+            &metadata.node_id,
+            &metadata.commitment_atx_id,
+        ),
+        labels_per_unit: metadata.labels_per_unit,
+        num_units: metadata.num_units,
+        total_labels: metadata.total_labels(),
+        total_size: metadata.total_size(),
+        max_file_size: metadata.max_file_size,
+        num_files: metadata.num_files(),
+        nonce: metadata.nonce,
+        last_position: metadata.last_position,
+    };
+    println!("{}", serde_json::to_string_pretty(&view)?);
+    Ok(())
+}
+
+fn check(args: CheckArgs) -> eyre::Result<()> {
+    let report = pos_verification::check_integrity(
+        &args.datadir,
+        args.fraction,
+        ScryptParams::new(args.n, 1, 1),
+        Some(args.sampling.seed()),
+    )?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    eyre::ensure!(report.is_valid(), "POS data is corrupted");
+    Ok(())
+}
+
+fn main() -> eyre::Result<()> {
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    match Cli::parse().command {
+        Commands::Prove(args) => prove(args),
+        Commands::Verify(args) => verify(args),
+        Commands::Inspect(args) => inspect(args),
+        Commands::Check(args) => check(args),
+    }
+}
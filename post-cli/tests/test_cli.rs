@@ -0,0 +1,102 @@
+use std::process::Command;
+
+use post::initialize::{CpuInitializer, Initialize};
+
+fn post_cli() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_post-cli"))
+}
+
+const PROOF_CFG: [&str; 8] = [
+    "--k1",
+    "23",
+    "--k2",
+    "32",
+    "--k3",
+    "10",
+    "--pow-difficulty",
+    "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+];
+
+#[test]
+fn test_prove_verify_inspect() {
+    let datadir = tempfile::tempdir().unwrap();
+    let outdir = tempfile::tempdir().unwrap();
+    CpuInitializer::new(post::config::ScryptParams::new(2, 1, 1))
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            256 * 16,
+            31,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let proof = outdir.path().join("proof.json");
+    let metadata = outdir.path().join("proof_metadata.json");
+
+    let status = post_cli()
+        .arg("prove")
+        .arg("--datadir")
+        .arg(datadir.path())
+        .args(["--challenge", &hex::encode([7u8; 32])])
+        .arg("--output")
+        .arg(&proof)
+        .arg("--metadata-output")
+        .arg(&metadata)
+        .args(PROOF_CFG)
+        .args(["--nonces", "32", "--randomx-mode", "light"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let verify = |scrypt_n: &str, extra: &[&str]| {
+        post_cli()
+            .arg("verify")
+            .arg("--proof")
+            .arg(&proof)
+            .arg("--metadata")
+            .arg(&metadata)
+            .args(PROOF_CFG)
+            .args(["--min-num-units", "1", "--labels-per-unit", "4096"])
+            .args(["-n", scrypt_n])
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    assert!(verify("2", &[]).status.success());
+
+    let output = verify("2", &["--report"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(0, report["failures"].as_array().unwrap().len());
+    assert_eq!(10, report["k3"].as_array().unwrap().len());
+
+    // The proof is invalid with different scrypt parameters
+    let output = verify("4", &[]);
+    assert!(!output.status.success());
+
+    let output = post_cli()
+        .arg("inspect")
+        .arg("--datadir")
+        .arg(datadir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let view: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(hex::encode([77u8; 32]), view["node_id"]);
+    assert_eq!(31, view["num_units"]);
+    assert_eq!(31 * 4096, view["total_labels"]);
+
+    let output = post_cli()
+        .arg("check")
+        .arg("--datadir")
+        .arg(datadir.path())
+        .args(["--fraction", "10", "-n", "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(127, report["files"].as_array().unwrap().len());
+}
@@ -4,19 +4,19 @@ use std::{
     cmp::min,
     env::temp_dir,
     fs::OpenOptions,
-    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    io::{BufWriter, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     time::{self, Duration},
 };
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
-use eyre::Context;
+use clap::{Args, Parser, Subcommand};
+use cli_args::{parse_difficulty, RandomXMode};
 use post::{
-    pow::{self, randomx, Prover as PowProver},
-    prove::{Prover, Prover8_56, ProvingParams},
+    pow::{randomx, Prover as PowProver},
+    reader::{ReadSettings, SingleFile},
+    throughput,
 };
 use rand::RngCore;
-use rayon::prelude::{ParallelBridge, ParallelIterator};
 use serde::Serialize;
 
 /// Profiler to measure the performance of generating the proof of space time
@@ -73,9 +73,18 @@ struct ProvingArgs {
     /// Higher value gives a better chance to find a proof within less passes over the POS data,
     /// but also slows down the process.
     ///
-    /// Must be a multiple of 16.
+    /// Must be a multiple of 16. '0' measures only reading the data.
     #[arg(short, long, default_value_t = 64, value_parser(parse_nonces))]
     nonces: u32,
+
+    /// Number of reads issued concurrently.
+    #[arg(long, default_value_t = ReadSettings::default().queue_depth)]
+    queue_depth: usize,
+
+    /// Read the data bypassing the page cache (Linux only).
+    /// Run with and without it to compare both read paths.
+    #[arg(long)]
+    direct_io: bool,
 }
 
 #[derive(Args, Debug)]
@@ -123,20 +132,6 @@ struct PowArgs {
     randomx_mode: RandomXMode,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
-enum RandomXMode {
-    /// Fast mode for proving. Requires 2080 MiB of memory.
-    Fast,
-    /// Light mode for verification. Requires only 256 MiB of memory, but runs significantly slower
-    Light,
-}
-
-impl std::fmt::Display for RandomXMode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.to_possible_value().unwrap().get_name().fmt(f)
-    }
-}
-
 fn parse_nonces(arg: &str) -> eyre::Result<u32> {
     let nonces = arg.parse()?;
     eyre::ensure!(nonces % 16 == 0, "nonces must be multiple of 16");
@@ -144,17 +139,11 @@ fn parse_nonces(arg: &str) -> eyre::Result<u32> {
     Ok(nonces)
 }
 
-fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
-    hex::decode(arg)?
-        .as_slice()
-        .try_into()
-        .wrap_err("invalid difficulty length")
-}
-
 #[derive(Debug, Serialize)]
 struct PerfResult {
     time_s: f64,
     speed_gib_s: f64,
+    direct_io: bool,
 }
 
 // Prepare file for benchmarking, possibly appending random data to it if needed.
@@ -201,13 +190,7 @@ fn main() -> eyre::Result<()> {
 
 /// Bench proving speed (going over POS data).
 fn proving(args: ProvingArgs) -> eyre::Result<()> {
-    let challenge = b"hello world, challenge me!!!!!!!";
-    let batch_size = 1024 * 1024;
     let total_size = args.data_size * 1024 * 1024 * 1024;
-    let params = ProvingParams {
-        difficulty: 0, // impossible to find a proof
-        pow_difficulty: [0xFF; 32],
-    };
 
     let file_path = args
         .data_file
@@ -218,29 +201,34 @@ fn proving(args: ProvingArgs) -> eyre::Result<()> {
         .num_threads(args.threads)
         .build()?;
 
-    let mut pow_prover = pow::MockProver::new();
-    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
-    let prover = Prover8_56::new(challenge, 0..args.nonces, params, &pow_prover, &[7; 32])?;
+    let settings = ReadSettings {
+        queue_depth: args.queue_depth,
+        direct_io: args.direct_io,
+        ..Default::default()
+    };
 
     let mut total_time = time::Duration::from_secs(0);
     let mut processed = 0;
 
     while total_time < Duration::from_secs(args.duration) {
         let file = util::open_without_cache(&file_path)?;
-        let reader = post::reader::read_from(BufReader::new(file), batch_size, total_size, None);
-        let start = time::Instant::now();
-        pool.install(|| {
-            reader.par_bridge().for_each(|batch| {
-                prover.prove(&batch.data, batch.pos, |_, _| None);
-            })
-        });
-        total_time += start.elapsed();
-        processed += args.data_size;
+        let source = SingleFile::new(file, &file_path, 0, total_size, total_size);
+        let measurement = throughput::measure_proving(
+            &source,
+            settings,
+            total_size,
+            total_size,
+            args.nonces,
+            &pool,
+        )?;
+        total_time += measurement.time;
+        processed += measurement.bytes;
     }
 
     let result = PerfResult {
         time_s: total_time.as_secs_f64(),
-        speed_gib_s: processed as f64 / total_time.as_secs_f64(),
+        speed_gib_s: processed as f64 / (1024 * 1024 * 1024) as f64 / total_time.as_secs_f64(),
+        direct_io: args.direct_io,
     };
     println!("{}", serde_json::to_string_pretty(&result)?);
 
@@ -264,12 +252,7 @@ fn pow(args: PowArgs) -> eyre::Result<()> {
         args.num_units, args.nonces,
     );
 
-    let randomx_flags = match args.randomx_mode {
-        RandomXMode::Fast => {
-            randomx::RandomXFlag::get_recommended_flags() | randomx::RandomXFlag::FLAG_FULL_MEM
-        }
-        RandomXMode::Light => randomx::RandomXFlag::get_recommended_flags(),
-    };
+    let randomx_flags = randomx::RandomXFlag::from(args.randomx_mode);
     eprintln!("RandomX flags: {}", randomx_flags);
 
     eprintln!("Initializing RandomX VMs...");
@@ -0,0 +1,50 @@
+//! Storage audit server
+//!
+//! Serves audits of the POS data over HTTP, so that i.e. a pool can periodically check
+//! that the POST service still holds its data (see [post::audit]).
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use axum::{routing::post, Router};
+use post::audit::{AuditError, AuditRequest, AuditResponse};
+
+use crate::client::PostService;
+
+async fn audit<S: PostService + Send + Sync + 'static>(
+    State(service): State<Arc<S>>,
+    Json(request): Json<AuditRequest>,
+) -> Result<Json<AuditResponse>, (StatusCode, String)> {
+    log::info!("auditing {} labels", request.indices.len());
+
+    let result = tokio::task::spawn_blocking(move || service.audit(&request))
+        .await
+        .map_err(|e| {
+            log::error!("internal error reading audited labels: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "error reading labels".into(),
+            )
+        })?;
+
+    match result {
+        Ok(response) => Ok(Json(response)),
+        // Invalid requests are the auditor's fault, failing to read the labels is ours
+        Err(e) if e.downcast_ref::<AuditError>().is_some() => {
+            log::warn!("rejected audit request: {e:?}");
+            Err((StatusCode::BAD_REQUEST, format!("{e:#}")))
+        }
+        Err(e) => {
+            log::error!("failed to read audited labels: {e:?}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))
+        }
+    }
+}
+
+/// Create the router of the audit server reading labels with the given service.
+pub fn new<S: PostService + Send + Sync + 'static>(service: Arc<S>) -> Router {
+    Router::new()
+        .route("/audit", post(audit::<S>))
+        .with_state(service)
+}
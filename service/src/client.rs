@@ -6,6 +6,7 @@
 
 use std::time::Duration;
 
+use post::audit::{AuditRequest, AuditResponse};
 use post::metadata::PostMetadata;
 pub(crate) use spacemesh_v1::post_service_client::PostServiceClient;
 use spacemesh_v1::{node_request, service_response};
@@ -45,6 +46,8 @@ pub trait PostService {
         proof: &post::prove::Proof<'a>,
         metadata: &post::metadata::ProofMetadata,
     ) -> eyre::Result<()>;
+
+    fn audit(&self, request: &AuditRequest) -> eyre::Result<AuditResponse>;
 }
 
 impl<T: PostService + ?Sized> PostService for std::sync::Arc<T> {
@@ -63,6 +66,10 @@ impl<T: PostService + ?Sized> PostService for std::sync::Arc<T> {
     fn get_metadata(&self) -> eyre::Result<PostMetadata> {
         self.as_ref().get_metadata()
     }
+
+    fn audit(&self, request: &AuditRequest) -> eyre::Result<AuditResponse> {
+        self.as_ref().audit(request)
+    }
 }
 
 impl<S: PostService> ServiceClient<S> {
@@ -219,8 +226,8 @@ impl<S: PostService> ServiceClient<S> {
                     })),
                 }
             }
-            Ok(ProofGenState::InProgress) => {
-                log::info!("proof generation in progress");
+            Ok(ProofGenState::InProgress { pass, percentage }) => {
+                log::info!("proof generation in progress (pass: {pass}, {percentage:.2}% of POS data processed)");
                 ServiceResponse {
                     kind: Some(service_response::Kind::GenProof(GenProofResponse {
                         status: GenProofStatus::Ok as i32,
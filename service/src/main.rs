@@ -1,13 +1,21 @@
-use std::{fs::read_to_string, path::PathBuf, time::Duration};
+use std::{
+    fs::read_to_string, future::IntoFuture, net::SocketAddr, path::PathBuf, sync::Arc,
+    time::Duration,
+};
 
-use clap::{Args, Parser, ValueEnum};
+use clap::{Args, Parser};
 use eyre::Context;
 use sysinfo::{Pid, ProcessExt, ProcessStatus, System, SystemExt};
+use tokio::net::TcpListener;
 use tokio::sync::oneshot::{self, error::TryRecvError, Receiver};
 use tonic::transport::{Certificate, Identity};
 
-use post::pow::randomx::RandomXFlag;
-use post_service::client;
+use cli_args::{parse_nonces, PostConfig, RandomXMode};
+use post::{
+    prove::{Nonces, ProvingOptions},
+    reader::ReadSettings,
+};
+use post_service::{audit, client};
 
 /// Post Service
 #[derive(Parser, Debug)]
@@ -39,53 +47,13 @@ struct Cli {
     /// watch PID and exit if it dies
     #[arg(long)]
     watch_pid: Option<sysinfo::Pid>,
-}
 
-#[derive(Args, Debug)]
-/// POST configuration - network parameters
-struct PostConfig {
-    /// The minimal number of units that must be initialized.
-    #[arg(long, default_value_t = 4)]
-    pub min_num_units: u32,
-    /// The maximal number of units that can be initialized.
-    #[arg(long, default_value_t = u32::MAX)]
-    pub max_num_units: u32,
-    ///  The number of labels per unit.
-    #[arg(long, default_value_t = 4294967296)]
-    pub labels_per_unit: u64,
-    /// K1 specifies the difficulty for a label to be a candidate for a proof
-    #[arg(long, default_value_t = 26)]
-    k1: u32,
-    /// K2 is the number of labels below the required difficulty required for a proof
-    #[arg(long, default_value_t = 37)]
-    k2: u32,
-    /// K3 is the size of the subset of proof indices that is validated
-    #[arg(long, default_value_t = 37)]
-    k3: u32,
-    /// difficulty for the nonce proof of work (aka "k2pow")
-    #[arg(
-        long,
-        default_value = "000dfb23b0979b4b000000000000000000000000000000000000000000000000",
-        value_parser(parse_difficulty)
-    )]
-    pow_difficulty: [u8; 32],
-    /// scrypt parameters for initialization
-    #[command(flatten)]
-    scrypt: ScryptParams,
-}
-
-/// Scrypt parameters for initialization
-#[derive(Args, Debug)]
-struct ScryptParams {
-    /// scrypt N parameter
-    #[arg(short, default_value_t = 8192)]
-    n: usize,
-    /// scrypt R parameter
-    #[arg(short, default_value_t = 1)]
-    r: usize,
-    /// scrypt P parameter
-    #[arg(short, default_value_t = 1)]
-    p: usize,
+    /// address to serve storage audits on (i.e. "0.0.0.0:9000")
+    ///
+    /// Lets i.e. a pool periodically check that the POS data is still held
+    /// by requesting random labels. Disabled if not set.
+    #[arg(long)]
+    audit_listen: Option<SocketAddr>,
 }
 
 #[derive(Args, Debug)]
@@ -101,23 +69,59 @@ struct PostSettings {
     ///
     /// Higher value gives a better chance to find a proof within less passes over the POS data,
     /// but also slows down the process.
-    #[arg(long, default_value_t = 128, value_parser(parse_nonces))]
-    nonces: usize,
+    ///
+    /// 'auto' means pick the largest number that keeps proving I/O-bound
+    /// (measured on the POS data before proving).
+    #[arg(long, default_value = "128", value_parser(parse_nonces))]
+    nonces: Nonces,
     /// modes of operation for RandomX
     #[arg(long, default_value_t = RandomXMode::Fast)]
     randomx_mode: RandomXMode,
-}
-
-/// RandomX modes of operation
-///
-/// They are interchangeable as they give the same results but have different
-/// purpose and memory requirements.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
-enum RandomXMode {
-    /// Fast mode for proving. Requires 2080 MiB of memory.
-    Fast,
-    /// Light mode for verification. Requires only 256 MiB of memory, but runs significantly slower
-    Light,
+    /// persist proof generation progress in the POS data directory
+    ///
+    /// Allows to resume generating a proof for the same challenge after a restart
+    /// instead of starting over.
+    #[arg(long)]
+    checkpoint: bool,
+    /// size of a single read of POS data (in bytes)
+    ///
+    /// Must be a multiple of 128.
+    #[arg(long, default_value_t = ReadSettings::default().batch_size)]
+    read_batch_size: usize,
+    /// number of reads of POS data issued concurrently on each disk
+    ///
+    /// Higher values help to saturate the bandwidth of fast (i.e. NVMe) disks.
+    #[arg(long, default_value_t = ReadSettings::default().queue_depth)]
+    read_queue_depth: usize,
+    /// read POS data bypassing the page cache (Linux only)
+    ///
+    /// Avoids evicting everything else from memory on every pass over the POS data.
+    /// Falls back to reading through the cache if the storage doesn't support it.
+    #[arg(long)]
+    direct_io: bool,
+    /// maximal number of passes (of `nonces` each) evaluated in a single read of POS data
+    ///
+    /// The k2pow of all of them is calculated up front. If a pass doesn't find a proof,
+    /// the following ones don't need to read the POS data again, at the cost of more CPU work.
+    #[arg(long, default_value_t = ProvingOptions::default().max_passes_per_read)]
+    max_passes_per_read: usize,
+    /// file to cache k2pow results in
+    ///
+    /// Cached results are reused when proving the same challenge again
+    /// (i.e. after a restart) instead of recalculating them.
+    #[arg(long)]
+    pow_cache: Option<PathBuf>,
+    /// address of the k2pow service to delegate k2pow calculation to (i.e. "http://localhost:3000")
+    ///
+    /// If not set, k2pow is calculated locally.
+    #[arg(long)]
+    k2pow_service: Option<String>,
+    /// address of the distributed proving coordinator to delegate proving to (i.e. "http://localhost:3200")
+    ///
+    /// The coordinator splits proving between workers having access to the POS data.
+    /// If not set, the proof is generated locally.
+    #[arg(long)]
+    coordinator: Option<String>,
 }
 
 /// TLS configuration
@@ -139,35 +143,6 @@ pub struct Tls {
     pub domain: Option<String>,
 }
 
-impl std::fmt::Display for RandomXMode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.to_possible_value().unwrap().get_name().fmt(f)
-    }
-}
-
-impl From<RandomXMode> for RandomXFlag {
-    fn from(val: RandomXMode) -> Self {
-        match val {
-            RandomXMode::Fast => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
-            RandomXMode::Light => RandomXFlag::get_recommended_flags(),
-        }
-    }
-}
-
-fn parse_nonces(arg: &str) -> eyre::Result<usize> {
-    let nonces = arg.parse()?;
-    eyre::ensure!(nonces % 16 == 0, "nonces must be multiple of 16");
-    eyre::ensure!(nonces / 16 <= 256, format!("max nonces is {}", 256 * 16));
-    Ok(nonces)
-}
-
-fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
-    hex::decode(arg)?
-        .as_slice()
-        .try_into()
-        .wrap_err("invalid difficulty length")
-}
-
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     let args = Cli::parse();
@@ -178,30 +153,35 @@ async fn main() -> eyre::Result<()> {
     log::info!("POST network parameters: {:?}", args.post_config);
     log::info!("POST proving settings: {:?}", args.post_settings);
 
-    let scrypt = post::config::ScryptParams::new(
-        args.post_config.scrypt.n,
-        args.post_config.scrypt.r,
-        args.post_config.scrypt.p,
-    );
-    let service = post_service::service::PostService::new(
+    let mut service = post_service::service::PostService::new(
         args.dir,
-        post::config::ProofConfig {
-            k1: args.post_config.k1,
-            k2: args.post_config.k2,
-            k3: args.post_config.k3,
-            pow_difficulty: args.post_config.pow_difficulty,
-        },
-        post::config::InitConfig {
-            min_num_units: args.post_config.min_num_units,
-            max_num_units: args.post_config.max_num_units,
-            labels_per_unit: args.post_config.labels_per_unit,
-            scrypt,
-        },
+        (&args.post_config.proof).into(),
+        (&args.post_config.init).into(),
         args.post_settings.nonces,
         args.post_settings.threads,
         args.post_settings.randomx_mode.into(),
+        args.post_settings.checkpoint,
     )
-    .wrap_err("creating Post Service")?;
+    .wrap_err("creating Post Service")?
+    .with_read_settings(ReadSettings {
+        batch_size: args.post_settings.read_batch_size,
+        queue_depth: args.post_settings.read_queue_depth,
+        direct_io: args.post_settings.direct_io,
+    })
+    .with_max_passes_per_read(args.post_settings.max_passes_per_read);
+    if let Some(path) = args.post_settings.pow_cache {
+        log::info!("caching k2pow results in {}", path.display());
+        let cache = post::pow::cache::FileCache::open(&path).wrap_err("opening k2pow cache")?;
+        service = service.with_pow_cache(Arc::new(cache));
+    }
+    if let Some(address) = args.post_settings.k2pow_service {
+        log::info!("using k2pow service on {address}");
+        service = service.with_k2pow_service(address);
+    }
+    if let Some(address) = args.post_settings.coordinator {
+        log::info!("using distributed proving coordinator on {address}");
+        service = service.with_coordinator(address);
+    }
 
     let tls = if let Some(tls) = args.tls {
         log::info!(
@@ -224,6 +204,14 @@ async fn main() -> eyre::Result<()> {
         None
     };
 
+    let service = Arc::new(service);
+    if let Some(addr) = args.audit_listen {
+        log::info!("serving storage audits on: {addr}");
+        let listener = TcpListener::bind(addr).await?;
+        let app = audit::new(service.clone());
+        tokio::spawn(axum::serve(listener, app.into_make_service()).into_future());
+    }
+
     let client = client::ServiceClient::new(args.address, tls, service)?;
     let client_handle = tokio::spawn(client.run(args.max_retries, args.reconnect_interval_s));
 
@@ -1,37 +1,101 @@
 //! Post Service
 
 use std::{
+    ops::Range,
     path::PathBuf,
-    sync::{atomic::AtomicBool, Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
+use distributed_prover::client::RemoteCoordinator;
 use eyre::Context;
+use k2pow_service::client::RemoteProver;
 use post::{
     metadata::{PostMetadata, ProofMetadata},
-    pow::randomx::{PoW, RandomXFlag},
-    prove::Proof,
+    pow::{
+        self,
+        cache::PowCache,
+        randomx::{PoW, RandomXFlag},
+    },
+    prove::{Nonces, ProgressReporter, Proof, ProvingOptions},
+    reader::ReadSettings,
     verification::Verifier,
 };
 
 #[derive(Debug)]
 pub enum ProofGenState {
-    InProgress,
-    Finished { proof: Proof<'static> },
+    InProgress {
+        /// The pass over POS data (numbered from 0)
+        pass: usize,
+        /// Percentage of POS data processed in the current pass
+        percentage: f64,
+    },
+    Finished {
+        proof: Proof<'static>,
+    },
 }
 
 #[derive(Debug)]
 struct ProofGenProcess {
     handle: std::thread::JoinHandle<eyre::Result<Proof<'static>>>,
     challenge: Vec<u8>,
+    progress: Arc<ProofGenProgress>,
+}
+
+/// Progress of the proof generation, updated by the proving thread.
+#[derive(Debug, Default)]
+struct ProofGenProgress {
+    pass: AtomicUsize,
+    read: AtomicU64,
+    total: AtomicU64,
+}
+
+impl ProofGenProgress {
+    fn state(&self) -> ProofGenState {
+        let read = self.read.load(Ordering::Relaxed);
+        let total = self.total.load(Ordering::Relaxed);
+        let percentage = match total {
+            0 => 0.0,
+            total => read as f64 / total as f64 * 100.0,
+        };
+        ProofGenState::InProgress {
+            pass: self.pass.load(Ordering::Relaxed),
+            percentage,
+        }
+    }
+}
+
+impl ProgressReporter for ProofGenProgress {
+    fn nonces_picked(&self, nonces: usize) {
+        log::info!("proving with {nonces} nonces per pass");
+    }
+
+    fn new_pass(&self, pass: usize, _nonces: Range<u32>) {
+        self.pass.store(pass, Ordering::Relaxed);
+        self.read.store(0, Ordering::Relaxed);
+    }
+
+    fn read_progress(&self, read: u64, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+        self.read.fetch_max(read, Ordering::Relaxed);
+    }
 }
 
 pub struct PostService {
     datadir: PathBuf,
     cfg: post::config::ProofConfig,
     init_cfg: post::config::InitConfig,
-    nonces: usize,
+    nonces: Nonces,
     threads: usize,
     pow_flags: RandomXFlag,
+    checkpoint: bool,
+    read: ReadSettings,
+    max_passes_per_read: usize,
+    pow_cache: Option<Arc<dyn PowCache>>,
+    k2pow_service: Option<String>,
+    coordinator: Option<String>,
     proof_generation: Mutex<Option<ProofGenProcess>>,
 
     verifier: Verifier,
@@ -43,9 +107,10 @@ impl PostService {
         datadir: PathBuf,
         cfg: post::config::ProofConfig,
         init_cfg: post::config::InitConfig,
-        nonces: usize,
+        nonces: Nonces,
         threads: usize,
         pow_flags: RandomXFlag,
+        checkpoint: bool,
     ) -> eyre::Result<Self> {
         Ok(Self {
             proof_generation: Mutex::new(None),
@@ -55,10 +120,48 @@ impl PostService {
             nonces,
             threads,
             pow_flags,
-            verifier: Verifier::new(Box::new(PoW::new(RandomXFlag::get_recommended_flags())?)),
+            checkpoint,
+            read: ReadSettings::default(),
+            max_passes_per_read: ProvingOptions::default().max_passes_per_read,
+            pow_cache: None,
+            k2pow_service: None,
+            coordinator: None,
+            verifier: Verifier::new(Box::new(PoW::new(RandomXFlag::get_recommended_flags())?), 1)?,
             stop: Arc::new(AtomicBool::new(false)),
         })
     }
+
+    /// Read POS data with the given settings while proving.
+    pub fn with_read_settings(mut self, read: ReadSettings) -> Self {
+        self.read = read;
+        self
+    }
+
+    /// Evaluate up to `passes` passes (of `nonces` each) in a single read of POS data.
+    pub fn with_max_passes_per_read(mut self, passes: usize) -> Self {
+        self.max_passes_per_read = passes;
+        self
+    }
+
+    /// Use the cache for k2pow results.
+    pub fn with_pow_cache(mut self, cache: Arc<dyn PowCache>) -> Self {
+        self.pow_cache = Some(cache);
+        self
+    }
+
+    /// Delegate k2pow calculation to the k2pow service available on `address`.
+    pub fn with_k2pow_service(mut self, address: String) -> Self {
+        self.k2pow_service = Some(address);
+        self
+    }
+
+    /// Delegate proving to the distributed proving coordinator available on `address`.
+    ///
+    /// The progress of proof generation isn't reported then.
+    pub fn with_coordinator(mut self, address: String) -> Self {
+        self.coordinator = Some(address);
+        self
+    }
 }
 
 impl crate::client::PostService for PostService {
@@ -89,7 +192,7 @@ impl crate::client::PostService for PostService {
                 }
             } else {
                 log::info!("proof generation in progress");
-                return Ok(ProofGenState::InProgress);
+                return Ok(process.progress.state());
             }
         }
 
@@ -103,15 +206,46 @@ impl crate::client::PostService for PostService {
         let datadir = self.datadir.clone();
         let nonces = self.nonces;
         let threads = self.threads;
+        let read = self.read;
+        let max_passes_per_read = self.max_passes_per_read;
         let stop = self.stop.clone();
+        let checkpoint = self.checkpoint;
+        let pow_cache = self.pow_cache.clone();
+        let k2pow_service = self.k2pow_service.clone();
+        let coordinator = self.coordinator.clone();
+        let progress = Arc::new(ProofGenProgress::default());
+        let reporter = progress.clone();
         *proof_gen = Some(ProofGenProcess {
             challenge,
             handle: std::thread::spawn(move || {
-                post::prove::generate_proof(&datadir, &ch, cfg, nonces, threads, pow_flags, stop)
+                if let Some(address) = coordinator {
+                    return RemoteCoordinator::new(&address)?.prove(&ch, &stop);
+                }
+                let pow_prover: Box<dyn pow::Prover + Sync> = match k2pow_service {
+                    Some(address) => Box::new(RemoteProver::new(&address)?),
+                    None => Box::new(PoW::new(pow_flags)?),
+                };
+                post::prove::generate_proof(
+                    &datadir,
+                    &ch,
+                    cfg,
+                    pow_prover.as_ref(),
+                    stop,
+                    ProvingOptions {
+                        nonces,
+                        max_passes_per_read,
+                        threads,
+                        read,
+                        checkpoint,
+                        pow_cache: pow_cache.as_deref(),
+                        reporter: reporter.as_ref(),
+                    },
+                )
             }),
+            progress: progress.clone(),
         });
 
-        Ok(ProofGenState::InProgress)
+        Ok(progress.state())
     }
 
     fn verify_proof(&self, proof: &Proof, metadata: &ProofMetadata) -> eyre::Result<()> {
@@ -123,6 +257,13 @@ impl crate::client::PostService for PostService {
     fn get_metadata(&self) -> eyre::Result<PostMetadata> {
         post::metadata::load(&self.datadir).wrap_err("loading POST metadata")
     }
+
+    fn audit(
+        &self,
+        request: &post::audit::AuditRequest,
+    ) -> eyre::Result<post::audit::AuditResponse> {
+        post::audit::respond(&self.datadir, request).wrap_err("reading audited labels")
+    }
 }
 
 impl Drop for PostService {
@@ -130,7 +271,7 @@ impl Drop for PostService {
         log::info!("shutting down post service");
         if let Some(process) = self.proof_generation.lock().unwrap().take() {
             log::debug!("killing proof generation process");
-            self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+            self.stop.store(true, Ordering::Relaxed);
             let _ = process.handle.join().unwrap();
             log::debug!("proof generation process exited");
         }
@@ -0,0 +1,96 @@
+use std::{future::IntoFuture, sync::Arc};
+
+use post::{
+    audit::{AuditRequest, AuditResponse},
+    config::{InitConfig, ProofConfig, ScryptParams},
+    initialize::{CpuInitializer, Initialize},
+    metadata::ProofMetadata,
+    pow::randomx::RandomXFlag,
+    prove::Nonces,
+};
+use reqwest::StatusCode;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn test_audit() {
+    let datadir = tempfile::tempdir().unwrap();
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            4,
+            100,
+            None,
+        )
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.path().into(),
+        ProofConfig {
+            k1: 8,
+            k2: 4,
+            k3: 4,
+            pow_difficulty: [0xFF; 32],
+        },
+        init_cfg,
+        Nonces::Fixed(16),
+        1,
+        RandomXFlag::get_recommended_flags(),
+        false,
+    )
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = post_service::audit::new(Arc::new(service));
+    tokio::spawn(axum::serve(listener, app.into_make_service()).into_future());
+
+    let client = reqwest::Client::new();
+    let request = AuditRequest::random(metadata.total_labels(), 32).unwrap();
+    let response = client
+        .post(format!("http://{addr}/audit"))
+        .json(&request)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let response: AuditResponse = response.json().await.unwrap();
+    post::audit::verify(
+        &request,
+        &response,
+        &ProofMetadata::new(metadata, [0; 32]),
+        &init_cfg,
+    )
+    .expect("labels should be valid");
+
+    // Labels out of POS data
+    let request = AuditRequest {
+        indices: vec![metadata.total_labels()],
+    };
+    let response = client
+        .post(format!("http://{addr}/audit"))
+        .json(&request)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // POS data that can't be read
+    std::fs::remove_file(datadir.path().join("postdata_0.bin")).unwrap();
+    let request = AuditRequest { indices: vec![0] };
+    let response = client
+        .post(format!("http://{addr}/audit"))
+        .json(&request)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -9,7 +9,7 @@ use tokio::sync::oneshot;
 use post::{
     initialize::{CpuInitializer, Initialize},
     metadata::PostMetadata,
-    prove::Proof,
+    prove::{Nonces, Proof},
 };
 use post_service::{
     client::{
@@ -40,9 +40,12 @@ async fn test_gen_proof_in_progress() {
     let mut test_server = TestServer::new().await;
 
     let mut service = MockPostService::new();
-    service
-        .expect_gen_proof()
-        .returning(|_| Ok(ProofGenState::InProgress));
+    service.expect_gen_proof().returning(|_| {
+        Ok(ProofGenState::InProgress {
+            pass: 0,
+            percentage: 0.0,
+        })
+    });
     let service = Arc::new(service);
     let client = test_server.create_client(service.clone());
     let client_handle = tokio::spawn(client.run(None, std::time::Duration::from_secs(1)));
@@ -255,9 +258,10 @@ async fn test_get_metadata(#[case] vrf_difficulty: Option<[u8; 32]>) {
         datadir.path().into(),
         cfg,
         init_cfg,
-        16,
+        Nonces::Fixed(16),
         1,
         post::pow::randomx::RandomXFlag::get_recommended_flags(),
+        false,
     )
     .unwrap();
 
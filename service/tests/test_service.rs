@@ -5,6 +5,7 @@ use post::{
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
     pow::randomx::RandomXFlag,
+    prove::Nonces,
 };
 use post_service::{client::PostService, service::ProofGenState};
 
@@ -45,9 +46,10 @@ fn test_generate_and_verify() {
         datadir.into_path(),
         cfg,
         init_cfg,
-        16,
+        Nonces::Fixed(16),
         1,
         pow_flags,
+        false,
     )
     .unwrap();
 
@@ -99,9 +101,10 @@ fn reject_invalid_challenge() {
         datadir.into_path(),
         cfg,
         init_cfg,
-        16,
+        Nonces::Fixed(16),
         1,
         RandomXFlag::get_recommended_flags(),
+        false,
     )
     .unwrap();
     assert!(service.gen_proof(vec![0xCA; 5]).is_err());
@@ -142,16 +145,17 @@ fn cannot_run_parallel_proof_gens() {
         datadir.into_path(),
         cfg,
         init_cfg,
-        16,
+        Nonces::Fixed(16),
         1,
         RandomXFlag::get_recommended_flags(),
+        false,
     )
     .unwrap();
 
     let result = service.gen_proof(vec![0xAA; 32]);
-    assert!(matches!(result, Ok(ProofGenState::InProgress)));
+    assert!(matches!(result, Ok(ProofGenState::InProgress { .. })));
     // Try to generate another proof with a different challenge
     assert!(service.gen_proof(vec![0xBB; 5]).is_err());
     // Try again with the same challenge
-    assert!(matches!(result, Ok(ProofGenState::InProgress)));
+    assert!(matches!(result, Ok(ProofGenState::InProgress { .. })));
 }
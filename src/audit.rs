@@ -0,0 +1,238 @@
+//! Remote storage audit.
+//!
+//! An auditor (i.e. a pool) picks random labels with [AuditRequest::random] and sends them
+//! to the prover. The prover reads the labels from its POS data with [respond]
+//! and the auditor checks them with [verify] by regenerating them.
+//! As the labels are picked randomly for each audit, the prover can't answer
+//! without holding (or regenerating) its POS data.
+//!
+//! ## Threat model
+//!
+//! An audit catches POS data that was lost, corrupted or initialized for another identity,
+//! and a service that can't read its data in time (i.e. a disk that went missing).
+//! It doesn't prove the data is stored against a prover willing to regenerate the
+//! requested labels on demand: with the network's scrypt parameters a label is regenerated
+//! (in parallel or on a GPU) faster than it is read from a disk. The auditor expects the answer
+//! within a [deadline] that budgets a random disk read per label ([LABEL_READ_BUDGET]),
+//! which only bounds how much compute such a prover must keep at hand.
+//! Holding the POS data is proven by the proofs of space-time, not by audits.
+use std::{path::Path, time::Duration};
+
+use eyre::Context;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+use crate::{
+    config::{InitConfig, ScryptParams},
+    initialize::{calc_commitment, generate_label, LABEL_SIZE},
+    layout,
+    metadata::{self, ProofMetadata},
+};
+
+/// Maximal number of labels that can be requested in a single audit.
+pub const MAX_AUDIT_LABELS: usize = 4096;
+
+/// Time allowed for an audit on top of reading the labels (network round-trip, opening the POS data).
+pub const AUDIT_LATENCY: Duration = Duration::from_secs(1);
+
+/// Time allowed for reading a single label: a random read on a hard disk (seek and rotation).
+pub const LABEL_READ_BUDGET: Duration = Duration::from_millis(15);
+
+/// Labels the prover is asked for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRequest {
+    /// Indices of the labels (in the entire POS data)
+    pub indices: Vec<u64>,
+}
+
+impl AuditRequest {
+    /// Pick `count` random labels out of `num_labels`.
+    pub fn random(num_labels: u64, count: usize) -> Result<Self, AuditError> {
+        if num_labels == 0 {
+            return Err(AuditError::NoLabels);
+        }
+        let mut rng = rand::thread_rng();
+        Ok(Self {
+            indices: (0..count).map(|_| rng.gen_range(0..num_labels)).collect(),
+        })
+    }
+}
+
+/// Labels read by the prover, in the order of the requested indices.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditResponse {
+    #[serde_as(as = "Vec<Hex>")]
+    pub labels: Vec<[u8; LABEL_SIZE]>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuditError {
+    #[error("there are no labels to audit")]
+    NoLabels,
+    #[error("too many labels requested: {0} (max {MAX_AUDIT_LABELS})")]
+    TooManyLabels(usize),
+    #[error("index {index} is out of POS data (labels: {num_labels})")]
+    IndexOutOfRange { index: u64, num_labels: u64 },
+    #[error("invalid number of labels (expected: {expected}, got: {got})")]
+    InvalidLabelsLen { expected: usize, got: usize },
+    #[error("invalid label at index {index} (expected: {expected:?}, got: {got:?})")]
+    InvalidLabel {
+        index: u64,
+        expected: [u8; LABEL_SIZE],
+        got: [u8; LABEL_SIZE],
+    },
+}
+
+/// Default deadline for answering an audit of `count` labels:
+/// the [AUDIT_LATENCY] and a [LABEL_READ_BUDGET] for every label.
+///
+/// See the [module docs](self) for what meeting it proves.
+pub fn deadline(count: usize) -> Duration {
+    AUDIT_LATENCY + LABEL_READ_BUDGET * count as u32
+}
+
+fn check_request(request: &AuditRequest, num_labels: u64) -> Result<(), AuditError> {
+    if request.indices.len() > MAX_AUDIT_LABELS {
+        return Err(AuditError::TooManyLabels(request.indices.len()));
+    }
+    if let Some(&index) = request.indices.iter().find(|&&idx| idx >= num_labels) {
+        return Err(AuditError::IndexOutOfRange { index, num_labels });
+    }
+    Ok(())
+}
+
+/// Read the labels requested in `request` from POS data in `datadir`.
+pub fn respond(datadir: &Path, request: &AuditRequest) -> eyre::Result<AuditResponse> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    let num_labels = metadata.last_position.unwrap_or(metadata.total_labels());
+    check_request(request, num_labels)?;
+
+    let source = layout::load(datadir)?.source(datadir, &metadata)?;
+    let labels_per_file = metadata.max_file_size / LABEL_SIZE as u64;
+    let mut labels = Vec::with_capacity(request.indices.len());
+    for &index in &request.indices {
+        let file = (index / labels_per_file) as usize;
+        let offset = (index % labels_per_file) * LABEL_SIZE as u64;
+        let mut label = [0u8; LABEL_SIZE];
+        let read = source
+            .read_at(file, offset, &mut label)
+            .wrap_err_with(|| format!("reading label {index} from file {file}"))?;
+        eyre::ensure!(
+            read == LABEL_SIZE,
+            "label {index} is missing in file {file}"
+        );
+        labels.push(label);
+    }
+    Ok(AuditResponse { labels })
+}
+
+/// Verify that `response` holds valid labels requested in `request`
+/// from the POS data described by `metadata`.
+pub fn verify(
+    request: &AuditRequest,
+    response: &AuditResponse,
+    metadata: &ProofMetadata,
+    init_cfg: &InitConfig,
+) -> Result<(), AuditError> {
+    let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
+    check_request(request, num_labels)?;
+    if request.indices.len() != response.labels.len() {
+        return Err(AuditError::InvalidLabelsLen {
+            expected: request.indices.len(),
+            got: response.labels.len(),
+        });
+    }
+    let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+    for (&index, &got) in request.indices.iter().zip(&response.labels) {
+        verify_label(&commitment, init_cfg.scrypt, index, got)?;
+    }
+    Ok(())
+}
+
+fn verify_label(
+    commitment: &[u8; 32],
+    scrypt: ScryptParams,
+    index: u64,
+    got: [u8; LABEL_SIZE],
+) -> Result<(), AuditError> {
+    let expected = generate_label(commitment, scrypt, index);
+    if expected != got {
+        return Err(AuditError::InvalidLabel {
+            index,
+            expected,
+            got,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::initialize::{CpuInitializer, Initialize};
+
+    use super::*;
+
+    #[test]
+    fn audit_pos_data() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 100,
+            scrypt,
+        };
+        let datadir = tempfile::tempdir().unwrap();
+        let post_metadata = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 70, None)
+            .unwrap();
+        let metadata = ProofMetadata::new(post_metadata, [0; 32]);
+
+        let request = AuditRequest::random(post_metadata.total_labels(), 20).unwrap();
+        let mut response = respond(datadir.path(), &request).unwrap();
+        verify(&request, &response, &metadata, &init_cfg).unwrap();
+
+        // Tampered label
+        response.labels[3] = [0xAB; LABEL_SIZE];
+        assert!(matches!(
+            verify(&request, &response, &metadata, &init_cfg),
+            Err(AuditError::InvalidLabel { .. })
+        ));
+
+        // Missing label
+        response.labels.pop();
+        assert!(matches!(
+            verify(&request, &response, &metadata, &init_cfg),
+            Err(AuditError::InvalidLabelsLen { .. })
+        ));
+    }
+
+    #[test]
+    fn deadline_budgets_disk_reads() {
+        assert_eq!(AUDIT_LATENCY, deadline(0));
+        assert_eq!(AUDIT_LATENCY + Duration::from_millis(960), deadline(64));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 2, 70, None)
+            .unwrap();
+
+        let request = AuditRequest {
+            indices: vec![0, 200],
+        };
+        assert!(respond(datadir.path(), &request).is_err());
+
+        let request = AuditRequest::random(200, MAX_AUDIT_LABELS + 1).unwrap();
+        assert!(respond(datadir.path(), &request).is_err());
+
+        assert!(matches!(
+            AuditRequest::random(0, 10),
+            Err(AuditError::NoLabels)
+        ));
+    }
+}
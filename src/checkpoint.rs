@@ -0,0 +1,385 @@
+//! Persisting proof generation progress
+//!
+//! Generating a proof can take hours - k2pow must be calculated for every nonce group
+//! and all POST data must be read (possibly several times). The checkpoint, stored next to
+//! the POST data, allows [generate_proof](crate::prove::generate_proof) to continue
+//! where it stopped after a restart, as long as it's asked to prove the same challenge.
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use crate::{metadata::save_json, pow, prove::ProvingParams};
+
+const CHECKPOINT_FILE_NAME: &str = "postdata_proving_checkpoint.json";
+
+/// How much (in bytes) of POST data must be processed before saving the next checkpoint.
+pub(crate) const CHECKPOINT_INTERVAL: u64 = 1024 * 1024 * 1024;
+
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Checkpoint {
+    #[serde_as(as = "Base64")]
+    pub challenge: [u8; 32],
+    /// Proving difficulty (inferred from K1) the indices were found with.
+    pub difficulty: u64,
+    /// Difficulty the k2pows were calculated for.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub pow_difficulty: [u8; 32],
+    /// K2 proofs of work found so far, per nonce group.
+    pub pows: BTreeMap<u32, u64>,
+    /// Nonces attempted in the current pass over POST data.
+    pub nonces: Range<u32>,
+    /// Number of nonces of a single pass (`nonces` might span several passes sharing a read).
+    /// It's 0 in checkpoints saved before it was recorded.
+    #[serde(default)]
+    pub nonces_per_pass: u32,
+    /// Position (in bytes) in POST data up to which all data was processed in the current pass.
+    pub position: u64,
+    /// Indices found (before `position`) for each nonce in the current pass.
+    pub indices: BTreeMap<u32, Vec<u64>>,
+}
+
+impl Checkpoint {
+    pub fn new(challenge: [u8; 32], params: &ProvingParams, nonces: Range<u32>) -> Self {
+        Self {
+            challenge,
+            difficulty: params.difficulty,
+            pow_difficulty: params.pow_difficulty,
+            nonces_per_pass: nonces.len() as u32,
+            nonces,
+            ..Default::default()
+        }
+    }
+
+    /// Number of nonces of a single pass, assuming a single pass per read if it wasn't recorded.
+    pub fn nonces_per_pass(&self) -> u32 {
+        match self.nonces_per_pass {
+            0 => self.nonces.len() as u32,
+            n => n,
+        }
+    }
+
+    /// Check if the checkpoint was created for proving the given challenge with the given params.
+    pub fn matches(&self, challenge: &[u8; 32], params: &ProvingParams) -> bool {
+        self.challenge == *challenge
+            && self.difficulty == params.difficulty
+            && self.pow_difficulty == params.pow_difficulty
+    }
+}
+
+/// Load the checkpoint stored in `datadir`, if any.
+pub fn load(datadir: &Path) -> eyre::Result<Option<Checkpoint>> {
+    let path = datadir.join(CHECKPOINT_FILE_NAME);
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(serde_json::from_reader(std::io::BufReader::new(
+        file,
+    ))?))
+}
+
+/// Atomically (over)write the checkpoint in `datadir`.
+pub fn save(datadir: &Path, checkpoint: &Checkpoint) -> eyre::Result<()> {
+    save_json(&datadir.join(CHECKPOINT_FILE_NAME), checkpoint)
+}
+
+/// Remove the checkpoint from `datadir` (if it exists).
+pub fn remove(datadir: &Path) -> eyre::Result<()> {
+    match std::fs::remove_file(datadir.join(CHECKPOINT_FILE_NAME)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Holds the state of proof generation and persists it in the POST data directory
+/// (if enabled).
+///
+/// Failing to persist the checkpoint is not fatal - proving continues, it just won't
+/// be able to resume from that point.
+pub(crate) struct Checkpointer<'a> {
+    datadir: Option<&'a Path>,
+    checkpoint: Mutex<Checkpoint>,
+}
+
+/// Load the checkpoint stored in `datadir` if it was created for proving `challenge` with `params`.
+pub(crate) fn resume(
+    datadir: &Path,
+    challenge: &[u8; 32],
+    params: &ProvingParams,
+) -> Option<Checkpoint> {
+    match load(datadir) {
+        Ok(Some(cp)) if cp.matches(challenge, params) => {
+            log::info!(
+                "resuming proof generation from checkpoint (nonces: {:?}, position: {}, k2pows: {})",
+                cp.nonces,
+                cp.position,
+                cp.pows.len(),
+            );
+            Some(cp)
+        }
+        Ok(Some(_)) => {
+            log::info!("ignoring proving checkpoint created for a different challenge");
+            None
+        }
+        Ok(None) => None,
+        Err(e) => {
+            log::warn!("failed to load proving checkpoint: {e:?}");
+            None
+        }
+    }
+}
+
+impl<'a> Checkpointer<'a> {
+    /// Create a checkpointer starting from `checkpoint`,
+    /// persisting the progress in `datadir` (if given).
+    pub(crate) fn new(datadir: Option<&'a Path>, checkpoint: Checkpoint) -> Self {
+        Self {
+            datadir,
+            checkpoint: Mutex::new(checkpoint),
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.datadir.is_some()
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Checkpoint> {
+        self.checkpoint.lock().unwrap()
+    }
+
+    fn persist(&self, checkpoint: &Checkpoint) {
+        if let Some(datadir) = self.datadir {
+            if let Err(e) = save(datadir, checkpoint) {
+                log::warn!("failed to save proving checkpoint: {e:?}");
+            }
+        }
+    }
+
+    /// Record a k2pow found for the nonce group.
+    pub(crate) fn add_pow(&self, nonce_group: u32, pow: u64) {
+        let mut checkpoint = self.lock();
+        checkpoint.pows.insert(nonce_group, pow);
+        self.persist(&checkpoint);
+    }
+
+    /// Record progress of the current pass.
+    pub(crate) fn advance(&self, position: u64, indices: BTreeMap<u32, Vec<u64>>) {
+        let mut checkpoint = self.lock();
+        // Progress snapshots might be reported out of order
+        if position > checkpoint.position {
+            checkpoint.position = position;
+            checkpoint.indices = indices;
+            self.persist(&checkpoint);
+        }
+    }
+
+    /// Start a new pass over POST data with the given nonces.
+    pub(crate) fn start_pass(&self, nonces: Range<u32>) {
+        let mut checkpoint = self.lock();
+        checkpoint.nonces = nonces;
+        checkpoint.position = 0;
+        checkpoint.indices.clear();
+        self.persist(&checkpoint);
+    }
+
+    /// Proof was found - the checkpoint is no longer needed.
+    pub(crate) fn finish(&self) {
+        if let Some(datadir) = self.datadir {
+            if let Err(e) = remove(datadir) {
+                log::warn!("failed to remove proving checkpoint: {e:?}");
+            }
+        }
+    }
+}
+
+/// PoW prover reusing k2pows from the checkpoint and recording newly found ones.
+pub(crate) struct CheckpointedPoW<'a, P> {
+    pub(crate) inner: &'a P,
+    pub(crate) checkpointer: &'a Checkpointer<'a>,
+}
+
+impl<P: pow::Prover> pow::Prover for CheckpointedPoW<'_, P> {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, pow::Error> {
+        if let Some(pow) = self.checkpointer.lock().pows.get(&(nonce_group as u32)) {
+            log::debug!("reusing proof of work for nonce group {nonce_group} from checkpoint");
+            return Ok(*pow);
+        }
+        let pow = self
+            .inner
+            .prove(nonce_group, challenge, difficulty, miner_id)?;
+        self.checkpointer.add_pow(nonce_group as u32, pow);
+        Ok(pow)
+    }
+}
+
+/// Tracks which part of POST data was processed in the current pass.
+///
+/// Batches are processed in parallel and complete out of order, so the position
+/// only moves past a batch once all the batches before it are done.
+#[derive(Debug, Default)]
+pub(crate) struct PassProgress {
+    pub(crate) indexes: HashMap<u32, Vec<u64>>,
+    position: u64,
+    last_checkpoint: u64,
+    // Batches completed ahead of `position` (start -> end)
+    completed: BTreeMap<u64, u64>,
+}
+
+impl PassProgress {
+    pub(crate) fn new(position: u64, indexes: HashMap<u32, Vec<u64>>) -> Self {
+        Self {
+            indexes,
+            position,
+            last_checkpoint: position,
+            completed: BTreeMap::new(),
+        }
+    }
+
+    /// Mark batch of `len` bytes at `pos` as processed.
+    ///
+    /// Returns a snapshot of the progress (position and indices found before it)
+    /// if enough data was processed since the last one.
+    pub(crate) fn batch_done(
+        &mut self,
+        pos: u64,
+        len: u64,
+    ) -> Option<(u64, BTreeMap<u32, Vec<u64>>)> {
+        self.completed.insert(pos, pos + len);
+        while let Some(end) = self.completed.remove(&self.position) {
+            self.position = end;
+        }
+        if self.position - self.last_checkpoint < CHECKPOINT_INTERVAL {
+            return None;
+        }
+        self.last_checkpoint = self.position;
+
+        let labels = self.position / crate::initialize::LABEL_SIZE as u64;
+        let indices = self
+            .indexes
+            .iter()
+            .map(|(&nonce, indices)| {
+                let found = indices.iter().copied().filter(|&idx| idx < labels);
+                (nonce, found.collect())
+            })
+            .collect();
+        Some((self.position, indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::pow::Prover as _;
+
+    #[test]
+    fn save_and_load() {
+        let datadir = tempfile::tempdir().unwrap();
+        assert_eq!(None, load(datadir.path()).unwrap());
+
+        let checkpoint = Checkpoint {
+            challenge: [7; 32],
+            difficulty: 77,
+            pow_difficulty: [0xFF; 32],
+            pows: BTreeMap::from([(0, 1), (1, 2)]),
+            nonces: 0..32,
+            nonces_per_pass: 16,
+            position: 1024,
+            indices: BTreeMap::from([(3, vec![1, 2, 3])]),
+        };
+        save(datadir.path(), &checkpoint).unwrap();
+        assert_eq!(Some(checkpoint.clone()), load(datadir.path()).unwrap());
+
+        remove(datadir.path()).unwrap();
+        assert_eq!(None, load(datadir.path()).unwrap());
+        // Removing not existing checkpoint is fine
+        remove(datadir.path()).unwrap();
+
+        // Saved before the nonces per pass were recorded
+        let checkpoint = Checkpoint {
+            nonces_per_pass: 0,
+            ..checkpoint
+        };
+        assert_eq!(32, checkpoint.nonces_per_pass());
+    }
+
+    #[test]
+    fn ignores_checkpoint_for_other_challenge() {
+        let datadir = tempfile::tempdir().unwrap();
+        let params = ProvingParams {
+            difficulty: 1,
+            pow_difficulty: [0xFF; 32],
+        };
+        let checkpoint = Checkpoint {
+            position: 1024,
+            ..Checkpoint::new([1; 32], &params, 16..32)
+        };
+        save(datadir.path(), &checkpoint).unwrap();
+
+        assert_eq!(Some(checkpoint), resume(datadir.path(), &[1; 32], &params));
+        assert_eq!(None, resume(datadir.path(), &[2; 32], &params));
+    }
+
+    #[test]
+    fn reuses_pows_from_checkpoint() {
+        let datadir = tempfile::tempdir().unwrap();
+        let params = ProvingParams {
+            difficulty: 1,
+            pow_difficulty: [0xFF; 32],
+        };
+        let checkpointer = Checkpointer::new(
+            Some(datadir.path()),
+            Checkpoint::new([0; 32], &params, 0..32),
+        );
+        checkpointer.add_pow(0, 7);
+
+        let mut inner = pow::MockProver::new();
+        inner
+            .expect_prove()
+            .once()
+            .withf(|&nonce_group, _, _, _| nonce_group == 1)
+            .returning(|_, _, _, _| Ok(9));
+        let prover = CheckpointedPoW {
+            inner: &inner,
+            checkpointer: &checkpointer,
+        };
+        assert_eq!(7, prover.prove(0, &[0; 8], &[0xFF; 32], &[0; 32]).unwrap());
+        assert_eq!(9, prover.prove(1, &[0; 8], &[0xFF; 32], &[0; 32]).unwrap());
+
+        let saved = load(datadir.path()).unwrap().unwrap();
+        assert_eq!(BTreeMap::from([(0, 7), (1, 9)]), saved.pows);
+    }
+
+    #[test]
+    fn pass_progress_advances_over_contiguous_batches() {
+        let batch = CHECKPOINT_INTERVAL / 2;
+        let mut progress = PassProgress::new(0, HashMap::new());
+        progress
+            .indexes
+            .insert(1, vec![0, batch / 16 + 1, 2 * batch / 16]);
+
+        // Out of order
+        assert_eq!(None, progress.batch_done(batch, batch));
+        let (pos, indices) = progress.batch_done(0, batch).unwrap();
+        assert_eq!(2 * batch, pos);
+        assert_eq!(vec![0, batch / 16 + 1], indices[&1]);
+
+        assert_eq!(None, progress.batch_done(3 * batch, batch));
+        assert_eq!(None, progress.batch_done(2 * batch, batch - 16));
+    }
+}
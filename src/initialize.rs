@@ -1,6 +1,6 @@
 use std::{
     error::Error,
-    fs::{create_dir_all, File},
+    fs::{create_dir_all, OpenOptions},
     io::Write,
     ops::Range,
     path::Path,
@@ -10,11 +10,18 @@ use mockall::automock;
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use scrypt_jane::scrypt::scrypt;
 
-use crate::{config::ScryptParams, metadata::PostMetadata};
+use crate::{
+    config::ScryptParams,
+    layout::{self, Layout},
+    metadata::{self, PostMetadata},
+};
 
 pub const LABEL_SIZE: usize = 16;
 pub const ENTIRE_LABEL_SIZE: usize = 32;
 
+/// Number of labels initialized between two progress checkpoints.
+pub const CHECKPOINT_INTERVAL: u64 = 16 * 1024 * 1024;
+
 pub fn calc_commitment(node_id: &[u8; 32], commitment_atx_id: &[u8; 32]) -> [u8; 32] {
     let mut hasher = blake3::Hasher::new();
     hasher.update(node_id);
@@ -30,6 +37,12 @@ pub struct VrfNonce {
 
 #[automock]
 pub trait Initialize {
+    /// Initialize POST data in `datadir`.
+    ///
+    /// Progress is checkpointed into the metadata file (`last_position`) every
+    /// [CHECKPOINT_INTERVAL] labels. If `datadir` contains an interrupted initialization
+    /// with the same parameters, it is resumed from the last checkpoint,
+    /// carrying over the best VRF nonce found so far.
     #[allow(clippy::too_many_arguments)]
     fn initialize(
         &mut self,
@@ -43,6 +56,7 @@ pub trait Initialize {
     ) -> Result<PostMetadata, Box<dyn Error>> {
         // Ensure that datadir exists
         create_dir_all(datadir)?;
+        let layout = layout::load(datadir)?;
 
         let commitment = calc_commitment(node_id, commitment_atx_id);
 
@@ -52,34 +66,207 @@ pub trait Initialize {
         if total_labels % labels_per_file != 0 {
             files_number += 1;
         }
-        let mut nonce = None;
-        for file_id in 0..files_number {
-            let mut post_data = File::create(datadir.join(format!("postdata_{}.bin", file_id)))?;
-            let index = file_id * labels_per_file;
-            let labels = index..total_labels.min(index + labels_per_file);
-            let new_nonce =
-                self.initialize_to(&mut post_data, &commitment, labels, vrf_difficulty)?;
-            if let Some(n) = new_nonce {
-                vrf_difficulty = Some(n.label);
-                nonce = Some(n);
-            }
-        }
 
-        let metadata = PostMetadata {
+        let mut metadata = PostMetadata {
             node_id: *node_id,
             commitment_atx_id: *commitment_atx_id,
             labels_per_unit,
             num_units,
-            max_file_size: labels_per_file * 16,
-            nonce: nonce.map(|n| n.index),
-            last_position: None,
+            max_file_size: labels_per_file * LABEL_SIZE as u64,
+            nonce: None,
+            last_position: Some(0),
         };
-        let metadata_file = File::create(datadir.join("postdata_metadata.json"))?;
-        serde_json::to_writer_pretty(metadata_file, &metadata)?;
+
+        let mut position = 0;
+        let mut nonce = None;
+        match metadata::load(datadir) {
+            Ok(existing) if same_layout(&existing, &metadata) => match existing.last_position {
+                None => {
+                    log::info!("POST data in {} is already initialized", datadir.display());
+                    return Ok(existing);
+                }
+                Some(last_position) => {
+                    position = resume_position(datadir, &layout, &existing, last_position)?;
+                    log::info!("resuming initialization from label {position}/{total_labels}");
+                    if let Some(index) = existing.nonce {
+                        let n = recover_vrf_nonce(self, &commitment, index)?;
+                        log::info!("recovered VRF nonce: {n:?}");
+                        if vrf_difficulty.is_some() {
+                            vrf_difficulty = Some(n.label);
+                        }
+                        nonce = Some(n);
+                    }
+                }
+            },
+            Ok(existing) => log::warn!(
+                "existing POST metadata doesn't match requested parameters ({existing:?}), initializing from scratch"
+            ),
+            Err(err) => log::debug!("not resuming initialization: {err}"),
+        }
+
+        for file_id in position / labels_per_file..files_number {
+            let file_start = file_id * labels_per_file;
+            let file_end = total_labels.min(file_start + labels_per_file);
+
+            // Drop everything written past the resume position (including partially written labels)
+            let mut post_data = layout.open_writer(
+                datadir,
+                metadata.max_file_size,
+                file_id,
+                position - file_start,
+            )?;
+
+            while position < file_end {
+                let labels = position..file_end.min(position + CHECKPOINT_INTERVAL);
+                let new_nonce = self.initialize_to(
+                    &mut post_data,
+                    &commitment,
+                    labels.clone(),
+                    vrf_difficulty,
+                )?;
+                if let Some(n) = new_nonce {
+                    vrf_difficulty = Some(n.label);
+                    nonce = Some(n);
+                }
+                position = labels.end;
+
+                // Make sure labels hit the disk before they are recorded in the checkpoint
+                post_data.sync_data()?;
+                metadata.nonce = nonce.map(|n| n.index);
+                metadata.last_position = Some(position);
+                metadata::save(datadir, &metadata)?;
+            }
+        }
+
+        metadata.nonce = nonce.map(|n| n.index);
+        metadata.last_position = None;
+        metadata::save(datadir, &metadata)?;
 
         Ok(metadata)
     }
 
+    /// Extend fully initialized POST data in `datadir` to `num_units` units.
+    ///
+    /// Labels of the new units continue after the existing ones. They fill up the last
+    /// (partial) file first and then go to new files. The VRF nonce search continues over
+    /// the new labels, starting from the nonce found so far.
+    ///
+    /// The extension is recorded in the metadata as an interrupted initialization,
+    /// so that [Initialize::initialize] can resume it if it's interrupted.
+    fn extend(
+        &mut self,
+        datadir: &Path,
+        num_units: u32,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<PostMetadata, Box<dyn Error>> {
+        let existing = metadata::load(datadir)?;
+        if existing.last_position.is_some() {
+            return Err("POST data is not fully initialized".into());
+        }
+        if num_units <= existing.num_units {
+            return Err(format!(
+                "POST data already has {} units (requested {num_units})",
+                existing.num_units
+            )
+            .into());
+        }
+        log::info!(
+            "extending POST data in {} from {} to {num_units} units",
+            datadir.display(),
+            existing.num_units
+        );
+
+        let extended = PostMetadata {
+            num_units,
+            last_position: Some(existing.total_labels()),
+            ..existing
+        };
+        if let Some(file) = layout::load(datadir)?.unassigned_file(extended.num_files()) {
+            return Err(format!("no directory is assigned to POS data file {file}").into());
+        }
+        metadata::save(datadir, &extended)?;
+
+        self.initialize(
+            datadir,
+            &extended.node_id,
+            &extended.commitment_atx_id,
+            extended.labels_per_unit,
+            num_units,
+            extended.max_file_size / LABEL_SIZE as u64,
+            vrf_difficulty,
+        )
+    }
+
+    /// Shrink fully initialized POST data in `datadir` to `num_units` units.
+    ///
+    /// Labels past the new number of units are dropped - the last kept file is truncated
+    /// and the following files are removed. If the VRF nonce falls outside of the kept labels,
+    /// a new one is searched for among them, which requires `vrf_difficulty`.
+    ///
+    /// The metadata is updated before the files are removed. If shrinking is interrupted,
+    /// shrinking again to the same number of units finishes it.
+    fn shrink(
+        &mut self,
+        datadir: &Path,
+        num_units: u32,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<PostMetadata, Box<dyn Error>> {
+        let existing = metadata::load(datadir)?;
+        if existing.last_position.is_some() {
+            return Err("POST data is not fully initialized".into());
+        }
+        if num_units == 0 || num_units > existing.num_units {
+            return Err(format!(
+                "cannot shrink POST data with {} units to {num_units} units",
+                existing.num_units
+            )
+            .into());
+        }
+        log::info!(
+            "shrinking POST data in {} from {} to {num_units} units",
+            datadir.display(),
+            existing.num_units
+        );
+
+        let mut shrunk = PostMetadata {
+            num_units,
+            ..existing
+        };
+        let total_labels = shrunk.total_labels();
+        if let Some(index) = existing.nonce.filter(|&index| index >= total_labels) {
+            if vrf_difficulty.is_none() {
+                return Err(format!(
+                    "VRF nonce at index {index} is dropped, a VRF difficulty is needed to search for a new one"
+                )
+                .into());
+            }
+            log::info!("VRF nonce at index {index} is dropped, searching for a new one");
+            let commitment = calc_commitment(&shrunk.node_id, &shrunk.commitment_atx_id);
+            shrunk.nonce = search_vrf_nonce(self, &commitment, 0..total_labels, vrf_difficulty)?
+                .map(|n| n.index);
+        }
+        metadata::save(datadir, &shrunk)?;
+
+        let layout = layout::load(datadir)?;
+        let last_file = shrunk.num_files() - 1;
+        let Some(path) = layout.file_path(datadir, last_file) else {
+            // Labels past the end of a single file are simply ignored
+            return Ok(shrunk);
+        };
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(shrunk.labels_in_file(last_file) as u64 * LABEL_SIZE as u64)?;
+        for file_id in last_file + 1.. {
+            match layout.file_path(datadir, file_id) {
+                Some(path) if path.exists() => std::fs::remove_file(path)?,
+                _ => break,
+            }
+        }
+
+        Ok(shrunk)
+    }
+
     fn initialize_to(
         &mut self,
         writer: &mut dyn Write,
@@ -89,6 +276,90 @@ pub trait Initialize {
     ) -> Result<Option<VrfNonce>, Box<dyn Error>>;
 }
 
+/// Check if `existing` metadata describes the same POST data layout as `requested`.
+fn same_layout(existing: &PostMetadata, requested: &PostMetadata) -> bool {
+    existing.node_id == requested.node_id
+        && existing.commitment_atx_id == requested.commitment_atx_id
+        && existing.labels_per_unit == requested.labels_per_unit
+        && existing.num_units == requested.num_units
+        && existing.max_file_size == requested.max_file_size
+}
+
+/// Find the position (label index) to resume an interrupted initialization from.
+///
+/// It's the last checkpointed position, unless some file written before it
+/// is shorter than expected - then it's the first label missing in that file.
+/// Labels in a [Layout::SingleFile] are trusted up to the last checkpointed position.
+fn resume_position(
+    datadir: &Path,
+    layout: &Layout,
+    metadata: &PostMetadata,
+    last_position: u64,
+) -> std::io::Result<u64> {
+    let labels_per_file = metadata.max_file_size / LABEL_SIZE as u64;
+    for (file_id, file_start) in (0..last_position)
+        .step_by(labels_per_file as usize)
+        .enumerate()
+    {
+        let expected = labels_per_file.min(last_position - file_start);
+        let Some(path) = layout.file_path(datadir, file_id) else {
+            break;
+        };
+        let written = match std::fs::metadata(&path) {
+            Ok(m) => m.len() / LABEL_SIZE as u64,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if written < expected {
+            log::warn!(
+                "{} has {written} labels, expected at least {expected}",
+                path.display()
+            );
+            return Ok(file_start + written);
+        }
+    }
+    Ok(last_position)
+}
+
+/// Regenerate the label at `index` to restore a VRF nonce found before.
+fn recover_vrf_nonce<I: Initialize + ?Sized>(
+    initializer: &mut I,
+    commitment: &[u8; 32],
+    index: u64,
+) -> Result<VrfNonce, Box<dyn Error>> {
+    initializer
+        .initialize_to(
+            &mut std::io::sink(),
+            commitment,
+            index..index + 1,
+            Some([0xFF; 32]),
+        )?
+        .ok_or_else(|| format!("failed to recover VRF nonce at index {index}").into())
+}
+
+/// Search for the VRF nonce (the smallest label below `vrf_difficulty`) among `labels`.
+fn search_vrf_nonce<I: Initialize + ?Sized>(
+    initializer: &mut I,
+    commitment: &[u8; 32],
+    labels: Range<u64>,
+    mut vrf_difficulty: Option<[u8; 32]>,
+) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+    if vrf_difficulty.is_none() {
+        return Ok(None);
+    }
+    let mut nonce = None;
+    for start in labels.clone().step_by(CHECKPOINT_INTERVAL as usize) {
+        let chunk = start..labels.end.min(start + CHECKPOINT_INTERVAL);
+        if let Some(n) =
+            initializer.initialize_to(&mut std::io::sink(), commitment, chunk, vrf_difficulty)?
+        {
+            vrf_difficulty = Some(n.label);
+            nonce = Some(n);
+        }
+    }
+    Ok(nonce)
+}
+
 pub struct CpuInitializer {
     scrypt_params: ScryptParams,
 }
@@ -154,13 +425,23 @@ pub(crate) fn generate_label(commitment: &[u8; 32], params: ScryptParams, index:
 }
 
 #[cfg(test)]
-mod tests {
-    use std::io::Read;
+pub(crate) mod tests {
+    use std::{fs::File, io::Read};
 
     use crate::{metadata, reader};
 
     use super::*;
 
+    /// Read all POS data files in `path` (ordered by their index).
+    pub(crate) fn read_files(path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+        for entry in reader::pos_files(path).unwrap() {
+            let mut file = File::open(entry.path()).unwrap();
+            file.read_to_end(&mut data).unwrap();
+        }
+        data
+    }
+
     #[test]
     fn test_initialize_to_file() {
         let labels = 7..27;
@@ -260,6 +541,226 @@ mod tests {
         assert_eq!(16000, total_size);
     }
 
+    #[test]
+    fn resumes_interrupted_initialization() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let vrf_difficulty = Some([0xFFu8; 32]);
+
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                &data_path.join("full"),
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                10,
+                300,
+                vrf_difficulty,
+            )
+            .unwrap();
+
+        // Simulate initialization interrupted after 450 labels were checkpointed.
+        // The second file has some more labels written after the checkpoint
+        // and ends with a partially written label.
+        let resumed_path = data_path.join("resumed");
+        std::fs::create_dir_all(&resumed_path).unwrap();
+        let commitment = calc_commitment(&[0u8; 32], &[0u8; 32]);
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        let mut file = File::create(resumed_path.join("postdata_0.bin")).unwrap();
+        let nonce = initializer
+            .initialize_to(&mut file, &commitment, 0..300, vrf_difficulty)
+            .unwrap();
+        let mut file = File::create(resumed_path.join("postdata_1.bin")).unwrap();
+        initializer
+            .initialize_to(&mut file, &commitment, 300..470, None)
+            .unwrap();
+        file.write_all(&[0xAB; 7]).unwrap();
+        let checkpoint = PostMetadata {
+            nonce: nonce.map(|n| n.index),
+            last_position: Some(450),
+            ..expected
+        };
+        metadata::save(&resumed_path, &checkpoint).unwrap();
+
+        let resumed = CpuInitializer::new(scrypt_params)
+            .initialize(
+                &resumed_path,
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                10,
+                300,
+                vrf_difficulty,
+            )
+            .unwrap();
+
+        assert_eq!(expected.nonce, resumed.nonce);
+        assert_eq!(None, resumed.last_position);
+        assert_eq!(resumed.nonce, metadata::load(&resumed_path).unwrap().nonce);
+
+        assert_eq!(
+            read_files(&data_path.join("full")),
+            read_files(&resumed_path)
+        );
+    }
+
+    #[test]
+    fn resumes_from_short_file() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 2, 50, None)
+            .unwrap();
+
+        // The checkpoint claims 150 labels but the second file was cut short.
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(data_path.join("postdata_1.bin"))
+            .unwrap();
+        file.set_len(16 * 20 + 3).unwrap();
+        std::fs::remove_file(data_path.join("postdata_3.bin")).unwrap();
+        let checkpoint = PostMetadata {
+            last_position: Some(150),
+            ..expected
+        };
+        metadata::save(data_path, &checkpoint).unwrap();
+        assert_eq!(
+            70,
+            resume_position(data_path, &Layout::Files, &checkpoint, 150).unwrap()
+        );
+
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 2, 50, None)
+            .unwrap();
+        for id in 0..4 {
+            let path = data_path.join(format!("postdata_{id}.bin"));
+            assert_eq!(16 * 50, path.metadata().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn extending_gives_same_result_as_initializing_all_units() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let vrf_difficulty = Some([0xFFu8; 32]);
+
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                &data_path.join("full"),
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                10,
+                300,
+                vrf_difficulty,
+            )
+            .unwrap();
+
+        // The last file of 4 units is partial (100 of 300 labels)
+        let extended_path = data_path.join("extended");
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        initializer
+            .initialize(
+                &extended_path,
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                4,
+                300,
+                vrf_difficulty,
+            )
+            .unwrap();
+        assert!(initializer
+            .extend(&extended_path, 4, vrf_difficulty)
+            .is_err());
+        let extended = initializer
+            .extend(&extended_path, 10, vrf_difficulty)
+            .unwrap();
+
+        assert_eq!(10, extended.num_units);
+        assert_eq!(expected.nonce, extended.nonce);
+        assert_eq!(None, extended.last_position);
+        assert_eq!(
+            extended.num_units,
+            metadata::load(&extended_path).unwrap().num_units
+        );
+
+        assert_eq!(
+            read_files(&data_path.join("full")),
+            read_files(&extended_path)
+        );
+    }
+
+    #[test]
+    fn shrinking_gives_same_result_as_initializing_less_units() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let vrf_difficulty = Some([0xFFu8; 32]);
+
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                &data_path.join("small"),
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                4,
+                300,
+                vrf_difficulty,
+            )
+            .unwrap();
+
+        let shrunk_path = data_path.join("shrunk");
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        initializer
+            .initialize(
+                &shrunk_path,
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                10,
+                300,
+                vrf_difficulty,
+            )
+            .unwrap();
+        // Pretend the VRF nonce is in the dropped labels to force searching for a new one
+        let metadata = metadata::load(&shrunk_path).unwrap();
+        metadata::save(
+            &shrunk_path,
+            &PostMetadata {
+                nonce: Some(900),
+                ..metadata
+            },
+        )
+        .unwrap();
+
+        assert!(initializer
+            .shrink(&shrunk_path, 11, vrf_difficulty)
+            .is_err());
+        // The new VRF nonce can't be searched for without the difficulty
+        assert!(initializer.shrink(&shrunk_path, 4, None).is_err());
+        assert_eq!(10, metadata::load(&shrunk_path).unwrap().num_units);
+        let shrunk = initializer.shrink(&shrunk_path, 4, vrf_difficulty).unwrap();
+
+        assert_eq!(4, shrunk.num_units);
+        assert_eq!(expected.nonce, shrunk.nonce);
+        assert_eq!(expected.nonce, metadata::load(&shrunk_path).unwrap().nonce);
+        assert!(!shrunk_path.join("postdata_2.bin").exists());
+        assert_eq!(
+            16 * 100,
+            shrunk_path.join("postdata_1.bin").metadata().unwrap().len()
+        );
+
+        assert_eq!(
+            read_files(&data_path.join("small")),
+            read_files(&shrunk_path)
+        );
+    }
+
     #[test]
     fn initialization_to_many_files_gives_same_result_as_single_file() {
         let scrypt_params = ScryptParams::new(4, 1, 1);
@@ -290,14 +791,6 @@ mod tests {
             )
             .unwrap();
 
-        let read_files = |path: &Path| -> Vec<u8> {
-            let mut data = Vec::new();
-            for entry in reader::pos_files(path).unwrap() {
-                let mut file = std::fs::File::open(entry.path()).unwrap();
-                file.read_to_end(&mut data).unwrap();
-            }
-            data
-        };
         // Read all files into memory from the many files version
         let many_files_data = read_files(&data_path.join("many"));
         // Read all files into memory from the single file version
@@ -0,0 +1,362 @@
+//! Layouts of POS data on storage.
+//!
+//! By default, labels are stored in `postdata_N.bin` files in the POS data directory.
+//! Alternatively, all labels can live contiguously in a single file or a raw block device
+//! (starting at some offset), avoiding a filesystem entirely, or the files can be spread
+//! over several directories (i.e. on separate disks) to use their aggregate bandwidth.
+//! The layout is recorded in a side file in the POS data directory, next to the metadata.
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    initialize::LABEL_SIZE,
+    metadata::{self, save_json, PostMetadata},
+    reader::{Directory, PostDataSource, SingleFile, Striped},
+};
+
+const LAYOUT_FILE_NAME: &str = "postdata_layout.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "Kind")]
+pub enum Layout {
+    /// `postdata_N.bin` files in the POS data directory.
+    #[default]
+    Files,
+    /// All labels stored contiguously in a single file or a block device
+    /// starting at `offset` (in bytes).
+    SingleFile {
+        #[serde(rename = "Path")]
+        path: PathBuf,
+        #[serde(rename = "Offset")]
+        offset: u64,
+    },
+    /// `postdata_N.bin` files spread over several directories.
+    Striped {
+        #[serde(rename = "Directories")]
+        directories: Vec<Stripe>,
+    },
+}
+
+/// Directory holding a range of POS data files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Stripe {
+    pub path: PathBuf,
+    /// Indices of the files held
+    pub files: Range<usize>,
+}
+
+/// Load the layout of POS data in `datadir`.
+///
+/// If it isn't recorded, the data is stored in [Layout::Files].
+pub fn load(datadir: &Path) -> eyre::Result<Layout> {
+    let path = datadir.join(LAYOUT_FILE_NAME);
+    match File::open(&path) {
+        Ok(file) => {
+            serde_json::from_reader(file).wrap_err_with(|| format!("parsing {}", path.display()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Layout::Files),
+        Err(e) => Err(e).wrap_err_with(|| format!("opening {}", path.display())),
+    }
+}
+
+/// Record the layout of POS data in `datadir`.
+///
+/// It must be done before initializing the data.
+pub fn save(datadir: &Path, layout: &Layout) -> eyre::Result<()> {
+    std::fs::create_dir_all(datadir)?;
+    save_json(&datadir.join(LAYOUT_FILE_NAME), layout)
+}
+
+impl Layout {
+    /// Spread `num_files` files evenly over `directories` (in consecutive ranges).
+    ///
+    /// The last directory also holds any files added later (i.e. by extending the POS data).
+    pub fn striped(directories: Vec<PathBuf>, num_files: usize) -> Self {
+        let count = directories.len();
+        let directories = directories
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let end = match i + 1 {
+                    last if last == count => usize::MAX,
+                    next => num_files * next / count,
+                };
+                Stripe {
+                    path,
+                    files: num_files * i / count..end,
+                }
+            })
+            .collect();
+        Layout::Striped { directories }
+    }
+
+    /// The first of files `0..num_files` that has no place to be stored in, if any.
+    pub(crate) fn unassigned_file(&self, num_files: usize) -> Option<usize> {
+        match self {
+            Layout::Striped { directories } => (0..num_files)
+                .find(|id| !directories.iter().any(|stripe| stripe.files.contains(id))),
+            _ => None,
+        }
+    }
+
+    /// Path of the POS data file `file_id`, unless the layout doesn't use separate files.
+    pub fn file_path(&self, datadir: &Path, file_id: usize) -> Option<PathBuf> {
+        let name = format!("postdata_{file_id}.bin");
+        match self {
+            Layout::Files => Some(datadir.join(name)),
+            Layout::SingleFile { .. } => None,
+            Layout::Striped { directories } => directories
+                .iter()
+                .find(|stripe| stripe.files.contains(&file_id))
+                .map(|stripe| stripe.path.join(name)),
+        }
+    }
+
+    /// Source of the POS data described by `metadata`, stored in this layout.
+    pub fn source(
+        &self,
+        datadir: &Path,
+        metadata: &PostMetadata,
+    ) -> eyre::Result<Box<dyn PostDataSource>> {
+        Ok(match self {
+            Layout::Files => Box::new(Directory::new(datadir)),
+            Layout::SingleFile { path, offset } => Box::new(
+                SingleFile::open(path, *offset, metadata)
+                    .wrap_err_with(|| format!("opening {}", path.display()))?,
+            ),
+            Layout::Striped { directories } => Box::new(Striped::new(
+                directories
+                    .iter()
+                    .map(|stripe| (stripe.path.as_path(), stripe.files.clone())),
+            )),
+        })
+    }
+
+    /// Open the storage of file `file_id` for writing labels, positioned at label `start`
+    /// (relative to the file).
+    ///
+    /// Separate files are created if needed and truncated at `start`.
+    pub(crate) fn open_writer(
+        &self,
+        datadir: &Path,
+        max_file_size: u64,
+        file_id: u64,
+        start: u64,
+    ) -> std::io::Result<File> {
+        let start = start * LABEL_SIZE as u64;
+        if let Layout::SingleFile { path, offset } = self {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            file.seek(SeekFrom::Start(offset + file_id * max_file_size + start))?;
+            return Ok(file);
+        }
+
+        let path = self.file_path(datadir, file_id as usize).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no directory is assigned to POS data file {file_id}"),
+            )
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.set_len(start)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(file)
+    }
+}
+
+/// Open the source of POS data in `datadir`, according to its layout.
+pub fn open_source(datadir: &Path) -> eyre::Result<Box<dyn PostDataSource>> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    load(datadir)?.source(datadir, &metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::ScryptParams,
+        initialize::{CpuInitializer, Initialize},
+        pos_verification::{check_integrity, verify_files},
+        reader,
+    };
+
+    use super::*;
+
+    #[test]
+    fn files_layout_by_default() {
+        let datadir = tempfile::tempdir().unwrap();
+        assert_eq!(Layout::Files, load(datadir.path()).unwrap());
+    }
+
+    #[test]
+    fn initialize_single_file() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let disk = tempfile::NamedTempFile::new().unwrap();
+        let layout = Layout::SingleFile {
+            path: disk.path().into(),
+            offset: 4096,
+        };
+        save(datadir.path(), &layout).unwrap();
+        assert_eq!(layout, load(datadir.path()).unwrap());
+
+        let metadata = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 70, None)
+            .unwrap();
+        assert_eq!(0, reader::pos_files(datadir.path()).unwrap().count());
+        assert_eq!(
+            4096 + metadata.total_size(),
+            disk.as_file().metadata().unwrap().len()
+        );
+
+        // The labels are the same as in the files layout
+        let files_dir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt)
+            .initialize(files_dir.path(), &[1; 32], &[2; 32], 100, 3, 70, None)
+            .unwrap();
+        let files = Directory::new(files_dir.path());
+        let single = open_source(datadir.path()).unwrap();
+        assert_eq!(files.files().unwrap().len(), single.files().unwrap().len());
+        for (file, other) in files.files().unwrap().iter().zip(single.files().unwrap()) {
+            assert_eq!(file.size, other.size);
+            let mut expected = vec![0; file.size as usize];
+            let mut data = vec![0; file.size as usize];
+            files.read_at(file.idx, 0, &mut expected).unwrap();
+            single.read_at(other.idx, 0, &mut data).unwrap();
+            assert_eq!(expected, data);
+        }
+
+        verify_files(datadir.path(), 100.0, None, None, scrypt, None).unwrap();
+        assert!(check_integrity(datadir.path(), 100.0, scrypt, None)
+            .unwrap()
+            .is_valid());
+    }
+
+    #[test]
+    fn spreads_files_evenly() {
+        let layout = Layout::striped(vec!["a".into(), "b".into(), "c".into()], 8);
+        let Layout::Striped { directories } = &layout else {
+            panic!("expected striped layout");
+        };
+        let ranges = directories
+            .iter()
+            .map(|s| s.files.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0..2, 2..5, 5..usize::MAX], ranges);
+        assert_eq!(
+            Some(PathBuf::from("b/postdata_4.bin")),
+            layout.file_path(Path::new("datadir"), 4)
+        );
+        // New files go to the last directory
+        assert_eq!(
+            Some(PathBuf::from("c/postdata_8.bin")),
+            layout.file_path(Path::new("datadir"), 8)
+        );
+        assert_eq!(None, layout.unassigned_file(100));
+
+        let layout = Layout::Striped {
+            directories: vec![Stripe {
+                path: "a".into(),
+                files: 0..2,
+            }],
+        };
+        assert_eq!(None, layout.unassigned_file(2));
+        assert_eq!(Some(2), layout.unassigned_file(3));
+    }
+
+    #[test]
+    fn initialize_striped() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let disks = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let layout = Layout::striped(disks.iter().map(|d| d.path().into()).collect(), 5);
+        save(datadir.path(), &layout).unwrap();
+
+        let metadata = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 70, None)
+            .unwrap();
+        assert_eq!(5, metadata.num_files());
+        assert_eq!(0, reader::pos_files(datadir.path()).unwrap().count());
+        assert_eq!(2, reader::pos_files(disks[0].path()).unwrap().count());
+        assert_eq!(3, reader::pos_files(disks[1].path()).unwrap().count());
+
+        let source = open_source(datadir.path()).unwrap();
+        let disks_of_files = source
+            .files()
+            .unwrap()
+            .iter()
+            .map(|f| f.disk)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0, 0, 1, 1, 1], disks_of_files);
+
+        verify_files(datadir.path(), 100.0, None, None, scrypt, None).unwrap();
+        assert!(check_integrity(datadir.path(), 100.0, scrypt, None)
+            .unwrap()
+            .is_valid());
+    }
+
+    #[test]
+    fn extend_striped() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let disks = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let layout = Layout::striped(disks.iter().map(|d| d.path().into()).collect(), 5);
+        save(datadir.path(), &layout).unwrap();
+
+        let mut initializer = CpuInitializer::new(scrypt);
+        initializer
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 70, None)
+            .unwrap();
+        let metadata = initializer.extend(datadir.path(), 5, None).unwrap();
+        assert_eq!(8, metadata.num_files());
+        assert_eq!(2, reader::pos_files(disks[0].path()).unwrap().count());
+        assert_eq!(6, reader::pos_files(disks[1].path()).unwrap().count());
+
+        verify_files(datadir.path(), 100.0, None, None, scrypt, None).unwrap();
+        assert!(check_integrity(datadir.path(), 100.0, scrypt, None)
+            .unwrap()
+            .is_valid());
+    }
+
+    #[test]
+    fn extend_striped_rejects_unassigned_files() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let disk = tempfile::tempdir().unwrap();
+        // Recorded before the last directory was open-ended
+        let layout = Layout::Striped {
+            directories: vec![Stripe {
+                path: disk.path().into(),
+                files: 0..5,
+            }],
+        };
+        save(datadir.path(), &layout).unwrap();
+
+        let mut initializer = CpuInitializer::new(scrypt);
+        initializer
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 70, None)
+            .unwrap();
+        assert!(initializer.extend(datadir.path(), 5, None).is_err());
+        // The metadata is untouched
+        let metadata = metadata::load(datadir.path()).unwrap();
+        assert_eq!(3, metadata.num_units);
+        assert_eq!(None, metadata.last_position);
+    }
+}
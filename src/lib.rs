@@ -1,12 +1,18 @@
+pub mod audit;
+pub mod checkpoint;
 mod cipher;
 mod compression;
 pub mod config;
 mod difficulty;
 pub mod initialize;
+pub mod layout;
 pub mod metadata;
 pub mod pos_verification;
 pub mod pow;
 pub mod prove;
 mod random_values_gen;
 pub mod reader;
+pub mod repair;
+pub mod reshard;
+pub mod throughput;
 pub mod verification;
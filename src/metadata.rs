@@ -1,4 +1,8 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    path::Path,
+};
 
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
@@ -60,6 +64,25 @@ pub fn load(datadir: &Path) -> eyre::Result<PostMetadata> {
     Ok(m)
 }
 
+/// Atomically (over)write the metadata file in `datadir`.
+pub fn save(datadir: &Path, metadata: &PostMetadata) -> eyre::Result<()> {
+    save_json(&datadir.join(METADATA_FILE_NAME), metadata)
+}
+
+/// Atomically (over)write a JSON file.
+///
+/// The value is written to a temporary file first and then renamed,
+/// so that a crash never leaves a partially written file behind.
+pub(crate) fn save_json<T: Serialize>(path: &Path, value: &T) -> eyre::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut tmp_file = File::create(&tmp_path)?;
+    serde_json::to_writer_pretty(&mut tmp_file, value)?;
+    tmp_file.flush()?;
+    tmp_file.sync_all()?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
 #[repr(C)]
 #[serde_as]
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -1,21 +1,35 @@
 //! Proof of Space data verification
 
-use std::{io::Read, io::Seek, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::Read,
+    io::Seek,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
 
 use itertools::Itertools;
 use rand::seq::IteratorRandom;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
+use serde::Serialize;
+use serde_with::{hex::Hex, serde_as};
 
 use crate::{
     config::ScryptParams,
     initialize::{calc_commitment, CpuInitializer, Initialize},
-    metadata,
+    layout,
+    metadata::{self, PostMetadata},
+    random_values_gen::Blake3Rng,
+    reader::PostDataSource,
 };
 
 #[derive(Debug, thiserror::Error)]
 pub enum VerificationError {
-    #[error("invalid label in file {idx} at offset {offset}")]
-    InvalidLabel { idx: usize, offset: u64 },
+    /// Offsets (in bytes) of the invalid labels found, by file index.
+    #[error("invalid labels in {}", fmt_invalid_labels(.0))]
+    InvalidLabels(BTreeMap<usize, Vec<u64>>),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("unknown error: {0}")]
@@ -24,49 +38,276 @@ pub enum VerificationError {
     InitError(String),
 }
 
+fn fmt_invalid_labels(invalid: &BTreeMap<usize, Vec<u64>>) -> String {
+    invalid
+        .iter()
+        .map(|(idx, offsets)| format!("file {idx} at offsets {offsets:?}"))
+        .join(", ")
+}
+
+/// Seed of the random selection of labels to verify.
+///
+/// Verification with the same seed checks the same labels, so that
+/// it can be reproduced (i.e. independently by a third party).
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct SamplingSeed(#[serde_as(as = "Hex")] pub [u8; 32]);
+
+impl SamplingSeed {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// Derive the seed from a challenge (i.e. given by an auditor),
+    /// so that the labels to verify can't be known before the challenge.
+    pub fn from_challenge(challenge: &[u8]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"pos-verification");
+        hasher.update(challenge);
+        Self(hasher.finalize().into())
+    }
+
+    /// RNG selecting labels to verify in a file starting at label `first_label`.
+    pub fn rng(&self, first_label: u64) -> impl rand::Rng {
+        Blake3Rng::from_seed(&[&self.0, &first_label.to_le_bytes()])
+    }
+}
+
+impl fmt::Display for SamplingSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl FromStr for SamplingSeed {
+    type Err = String;
+
+    /// Parse hex-encoded seed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let seed = hex::decode(s).map_err(|e| format!("invalid seed: {e}"))?;
+        seed.as_slice()
+            .try_into()
+            .map(Self)
+            .map_err(|_| format!("seed must be 32 bytes, got {}", seed.len()))
+    }
+}
+
+/// Verify `fraction` % of labels in POS data files in `datadir`.
+///
+/// The labels to verify are selected randomly with the `seed`.
+/// If it's not given, a random one is used (and logged).
 pub fn verify_files(
     datadir: &Path,           // path to POS data directory
     fraction: f64,            // total % of labels to verify
     from_file: Option<usize>, // inclusive
     to_file: Option<usize>,   // inclusive
     scrypt: ScryptParams,
+    seed: Option<SamplingSeed>,
 ) -> Result<(), VerificationError> {
     log::info!("verifying POS data in {}", datadir.display());
     let metadata = metadata::load(datadir)?;
+    verify_source(
+        layout::load(datadir)?.source(datadir, &metadata)?.as_ref(),
+        &metadata,
+        fraction,
+        from_file,
+        to_file,
+        scrypt,
+        seed,
+    )
+}
+
+/// Verify `fraction` % of labels in POS data described by `metadata` held by `source`.
+///
+/// All the files are verified, the error lists the invalid labels found in every file.
+/// See [verify_files].
+pub fn verify_source(
+    source: &dyn PostDataSource,
+    metadata: &PostMetadata,
+    fraction: f64,
+    from_file: Option<usize>,
+    to_file: Option<usize>,
+    scrypt: ScryptParams,
+    seed: Option<SamplingSeed>,
+) -> Result<(), VerificationError> {
+    let seed = seed.unwrap_or_else(SamplingSeed::random);
+    log::info!("sampling seed: {seed}");
 
     let from_file = from_file.unwrap_or(0);
     let to_file = to_file.unwrap_or(metadata.num_files() - 1);
     log::info!("verifying POS files {from_file} -> {to_file}");
 
+    let mut invalid_labels = BTreeMap::new();
     for idx in from_file..=to_file {
-        let file_path = datadir.join(format!("postdata_{}.bin", idx));
-        log::info!("verifying file {}", file_path.display());
-
-        let file = std::fs::File::open(file_path)?;
-        let reader = std::io::BufReader::new(file);
+        log::info!("verifying file {idx}");
+        let reader = std::io::BufReader::new(source.open(idx)?);
 
-        verify(reader, idx, fraction, &metadata, scrypt)?;
+        let labels_count = metadata.labels_in_file(idx) as u64;
+        let invalid = sample_labels(
+            reader,
+            idx,
+            0..labels_count,
+            fraction,
+            metadata,
+            scrypt,
+            &seed,
+        )?;
+        if !invalid.is_empty() {
+            log::warn!("found {} invalid label(s) in file {idx}", invalid.len());
+            invalid_labels.insert(idx, invalid);
+        }
     }
 
+    if !invalid_labels.is_empty() {
+        return Err(VerificationError::InvalidLabels(invalid_labels));
+    }
     Ok(())
 }
 
-fn verify<R: Read + Seek + Send>(
+/// Integrity report of POS data, see [check_integrity].
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    /// Seed of the random selection of labels verified.
+    pub seed: SamplingSeed,
+    /// Reports of POS data files that exist.
+    pub files: Vec<FileReport>,
+    /// Indexes of POS data files that don't exist.
+    pub missing_files: Vec<usize>,
+    /// Names of POS data files that shouldn't exist.
+    pub extra_files: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_valid(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.extra_files.is_empty()
+            && self.files.iter().all(FileReport::is_valid)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub idx: usize,
+    pub expected_size: u64,
+    pub actual_size: u64,
+    /// Number of labels verified.
+    pub labels_checked: usize,
+    /// Offsets (in bytes) of the invalid labels found.
+    pub invalid_labels: Vec<u64>,
+}
+
+impl FileReport {
+    pub fn is_valid(&self) -> bool {
+        self.expected_size == self.actual_size && self.invalid_labels.is_empty()
+    }
+}
+
+/// Check integrity of all POS data in `datadir`.
+///
+/// Unlike [verify_files], it doesn't stop on the first problem. It checks sizes
+/// of all the files, looks for missing and extra files and verifies `fraction` %
+/// of labels in every file (selected randomly with the `seed`), reporting all invalid ones.
+pub fn check_integrity(
+    datadir: &Path,
+    fraction: f64,
+    scrypt: ScryptParams,
+    seed: Option<SamplingSeed>,
+) -> Result<IntegrityReport, VerificationError> {
+    log::info!("checking integrity of POS data in {}", datadir.display());
+    let metadata = metadata::load(datadir)?;
+    let source = layout::load(datadir)?.source(datadir, &metadata)?;
+    check_source_integrity(source.as_ref(), &metadata, fraction, scrypt, seed)
+}
+
+/// Check integrity of POS data described by `metadata` held by `source`.
+///
+/// See [check_integrity].
+pub fn check_source_integrity(
+    source: &dyn PostDataSource,
+    metadata: &PostMetadata,
+    fraction: f64,
+    scrypt: ScryptParams,
+    seed: Option<SamplingSeed>,
+) -> Result<IntegrityReport, VerificationError> {
+    let seed = seed.unwrap_or_else(SamplingSeed::random);
+    log::info!("sampling seed: {seed}");
+    let num_files = metadata.num_files();
+    let mut files = source
+        .files()?
+        .into_iter()
+        .map(|file| (file.idx, file))
+        .collect::<HashMap<_, _>>();
+
+    let mut report = IntegrityReport {
+        seed,
+        files: Vec::new(),
+        missing_files: Vec::new(),
+        extra_files: Vec::new(),
+    };
+    for idx in 0..num_files {
+        let Some(file) = files.remove(&idx) else {
+            log::warn!("missing file {idx}");
+            report.missing_files.push(idx);
+            continue;
+        };
+        log::info!("checking file {}", file.name);
+
+        let labels_count = metadata.labels_in_file(idx) as u64;
+        // Sample only labels that are present in the file
+        let labels_present = labels_count.min(file.size / 16);
+        let invalid_labels = sample_labels(
+            std::io::BufReader::new(source.open(idx)?),
+            idx,
+            0..labels_present,
+            fraction,
+            metadata,
+            scrypt,
+            &seed,
+        )?;
+
+        report.files.push(FileReport {
+            idx,
+            expected_size: labels_count * 16,
+            actual_size: file.size,
+            labels_checked: labels_to_sample(labels_present, fraction),
+            invalid_labels,
+        });
+    }
+
+    report.extra_files = files
+        .into_values()
+        .sorted_by_key(|file| file.idx)
+        .map(|file| file.name)
+        .collect();
+
+    Ok(report)
+}
+
+fn labels_to_sample(labels_count: u64, fraction: f64) -> usize {
+    (labels_count as f64 * (fraction / 100.0)) as usize
+}
+
+/// Verify randomly sampled `fraction` % of the labels in `range` (relative to the file).
+///
+/// Returns the offsets of all invalid labels found (sorted).
+pub(crate) fn sample_labels<R: Read + Seek + Send>(
     mut labels: R,
     file_idx: usize,
+    range: Range<u64>,
     fraction: f64,
-    metadata: &metadata::PostMetadata,
+    metadata: &PostMetadata,
     scrypt_params: ScryptParams,
-) -> Result<(), VerificationError> {
+    seed: &SamplingSeed,
+) -> Result<Vec<u64>, VerificationError> {
     let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
 
-    let labels_count = metadata.labels_in_file(file_idx);
     let labels_offset = file_idx as u64 * metadata.max_file_size / 16;
-    let labels_to_verify = (labels_count as f64 * (fraction / 100.0)) as usize;
+    let labels_to_verify = labels_to_sample(range.end - range.start, fraction);
     log::info!("verifying {labels_to_verify} labels");
 
-    let mut rng = rand::thread_rng();
-    (0..labels_count as u64)
+    let mut rng = seed.rng(labels_offset);
+    let mut invalid = range
         .choose_multiple(&mut rng, labels_to_verify)
         .into_iter()
         .sorted()
@@ -77,29 +318,28 @@ fn verify<R: Read + Seek + Send>(
             Ok((index, label))
         })
         .par_bridge()
-        .map(|index_and_label| -> Result<(), VerificationError> {
-            let (index, label) = index_and_label?;
-            let mut expected_label = [0u8; 16];
-            let label_index = index + labels_offset;
+        .filter_map(
+            |index_and_label| -> Option<Result<u64, VerificationError>> {
+                let (index, label) = match index_and_label {
+                    Ok(index_and_label) => index_and_label,
+                    Err(e) => return Some(Err(e)),
+                };
+                let mut expected_label = [0u8; 16];
+                let label_index = index + labels_offset;
 
-            CpuInitializer::new(scrypt_params)
-                .initialize_to(
+                if let Err(e) = CpuInitializer::new(scrypt_params).initialize_to(
                     &mut expected_label.as_mut_slice(),
                     &commitment,
                     label_index..label_index + 1,
                     None,
-                )
-                .map_err(|e| VerificationError::InitError(format!("{e:?}")))?;
-
-            if label != expected_label {
-                return Err(VerificationError::InvalidLabel {
-                    idx: file_idx,
-                    offset: index * 16,
-                });
-            }
-            Ok(())
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+                ) {
+                    return Some(Err(VerificationError::InitError(format!("{e:?}"))));
+                }
 
-    Ok(())
+                (label != expected_label).then_some(Ok(index * 16))
+            },
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+    invalid.sort_unstable();
+    Ok(invalid)
 }
@@ -0,0 +1,332 @@
+//! Caching results of the k2pow
+//!
+//! Calculating the PoW with RandomX is expensive, but its result depends only
+//! on the challenge, the nonce group, the difficulty and the miner ID. Caching it
+//! avoids recalculating it when proving the same challenge again (i.e. after a restart
+//! or with a different number of nonces).
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+use super::{Error, Prover};
+
+/// Identifies a k2pow result.
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct CacheKey {
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 8],
+    pub nonce_group: u8,
+    #[serde_as(as = "Hex")]
+    pub difficulty: [u8; 32],
+    #[serde_as(as = "Hex")]
+    pub miner_id: [u8; 32],
+}
+
+/// Storage of k2pow results.
+pub trait PowCache: Send + Sync {
+    fn get(&self, key: &CacheKey) -> Option<u64>;
+    fn insert(&self, key: CacheKey, pow: u64);
+}
+
+/// Cache holding k2pows in memory only.
+#[derive(Debug, Default)]
+pub struct InMemoryCache {
+    pows: Mutex<HashMap<CacheKey, u64>>,
+}
+
+impl PowCache for InMemoryCache {
+    fn get(&self, key: &CacheKey) -> Option<u64> {
+        self.pows.lock().unwrap().get(key).copied()
+    }
+
+    fn insert(&self, key: CacheKey, pow: u64) {
+        self.pows.lock().unwrap().insert(key, pow);
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    #[serde(flatten)]
+    key: CacheKey,
+    pow: u64,
+}
+
+/// Maximal number of k2pows held by [FileCache].
+///
+/// A single challenge has at most 256 nonce groups, so it's exceeded only
+/// if the difficulty or the miner ID change often.
+const MAX_ENTRIES: usize = 1024;
+
+/// Cache persisting k2pows in a file, one JSON entry per line.
+///
+/// New k2pows are appended to the file. Only the k2pows of the latest challenge are kept:
+/// caching a k2pow of a new challenge (or exceeding [MAX_ENTRIES]) evicts the others
+/// and rewrites the file. Failing to write it is not fatal, the k2pow is still cached in memory.
+#[derive(Debug)]
+pub struct FileCache {
+    path: PathBuf,
+    pows: Mutex<HashMap<CacheKey, u64>>,
+}
+
+impl FileCache {
+    /// Open the cache stored in `path`. The file is created on first insert if it doesn't exist.
+    ///
+    /// Lines that can't be parsed (i.e. torn by a crash while appending) are skipped
+    /// and the file is rewritten without them.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pows = HashMap::new();
+        let mut skipped = 0;
+        for line in String::from_utf8_lossy(&content).lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => _ = pows.insert(entry.key, entry.pow),
+                Err(e) => {
+                    log::warn!("skipping invalid k2pow cache entry {line:?}: {e}");
+                    skipped += 1;
+                }
+            }
+        }
+        log::debug!("loaded {} k2pows from cache {}", pows.len(), path.display());
+
+        let cache = Self {
+            path: path.to_path_buf(),
+            pows: Mutex::new(pows),
+        };
+        if skipped > 0 {
+            if let Err(e) = cache.rewrite(&cache.pows.lock().unwrap()) {
+                log::warn!("failed to rewrite k2pow cache {}: {e:?}", path.display());
+            }
+        }
+        Ok(cache)
+    }
+
+    fn append(&self, entry: &Entry) -> eyre::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn rewrite(&self, pows: &HashMap<CacheKey, u64>) -> eyre::Result<()> {
+        let mut content = Vec::new();
+        for (&key, &pow) in pows {
+            serde_json::to_writer(&mut content, &Entry { key, pow })?;
+            content.push(b'\n');
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp_file = std::fs::File::create(&tmp_path)?;
+        tmp_file.write_all(&content)?;
+        tmp_file.sync_all()?;
+        std::fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl PowCache for FileCache {
+    fn get(&self, key: &CacheKey) -> Option<u64> {
+        self.pows.lock().unwrap().get(key).copied()
+    }
+
+    fn insert(&self, key: CacheKey, pow: u64) {
+        let mut pows = self.pows.lock().unwrap();
+        let size = pows.len();
+        pows.retain(|k, _| k.challenge == key.challenge);
+        if pows.len() >= MAX_ENTRIES {
+            pows.clear();
+        }
+        let evicted = pows.len() != size;
+        pows.insert(key, pow);
+
+        let result = match evicted {
+            true => self.rewrite(&pows),
+            false => self.append(&Entry { key, pow }),
+        };
+        if let Err(e) = result {
+            log::warn!("failed to save k2pow cache {}: {e:?}", self.path.display());
+        }
+    }
+}
+
+/// PoW prover consulting the cache before calculating the PoW.
+pub struct CachedProver<'a, P: ?Sized> {
+    inner: &'a P,
+    cache: Option<&'a dyn PowCache>,
+}
+
+impl<'a, P: ?Sized> CachedProver<'a, P> {
+    pub fn new(inner: &'a P, cache: Option<&'a dyn PowCache>) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl<P: Prover + ?Sized> Prover for CachedProver<'_, P> {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, Error> {
+        let key = CacheKey {
+            challenge: *challenge,
+            nonce_group,
+            difficulty: *difficulty,
+            miner_id: *miner_id,
+        };
+        if let Some(pow) = self.cache.and_then(|cache| cache.get(&key)) {
+            log::debug!("using cached proof of work for nonce group {nonce_group}");
+            return Ok(pow);
+        }
+        let pow = self
+            .inner
+            .prove(nonce_group, challenge, difficulty, miner_id)?;
+        if let Some(cache) = self.cache {
+            cache.insert(key, pow);
+        }
+        Ok(pow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pow::MockProver;
+
+    fn key(nonce_group: u8) -> CacheKey {
+        CacheKey {
+            challenge: [1; 8],
+            nonce_group,
+            difficulty: [2; 32],
+            miner_id: [3; 32],
+        }
+    }
+
+    #[test]
+    fn in_memory_cache() {
+        let cache = InMemoryCache::default();
+        assert_eq!(None, cache.get(&key(0)));
+        cache.insert(key(0), 7);
+        assert_eq!(Some(7), cache.get(&key(0)));
+        assert_eq!(None, cache.get(&key(1)));
+    }
+
+    #[test]
+    fn file_cache_persists_pows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("k2pow_cache.json");
+        {
+            let cache = FileCache::open(&path).unwrap();
+            assert_eq!(None, cache.get(&key(0)));
+            cache.insert(key(0), 7);
+            cache.insert(key(1), 8);
+        }
+        let cache = FileCache::open(&path).unwrap();
+        assert_eq!(Some(7), cache.get(&key(0)));
+        assert_eq!(Some(8), cache.get(&key(1)));
+    }
+
+    #[test]
+    fn file_cache_keeps_only_latest_challenge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("k2pow_cache.json");
+        let other = CacheKey {
+            challenge: [9; 8],
+            ..key(0)
+        };
+        {
+            let cache = FileCache::open(&path).unwrap();
+            cache.insert(key(0), 7);
+            cache.insert(key(1), 8);
+            assert_eq!(2, std::fs::read_to_string(&path).unwrap().lines().count());
+            cache.insert(other, 9);
+            assert_eq!(None, cache.get(&key(0)));
+        }
+        let cache = FileCache::open(&path).unwrap();
+        assert_eq!(None, cache.get(&key(0)));
+        assert_eq!(None, cache.get(&key(1)));
+        assert_eq!(Some(9), cache.get(&other));
+    }
+
+    #[test]
+    fn file_cache_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("k2pow_cache.json");
+        let cache = FileCache::open(&path).unwrap();
+        for i in 0..=MAX_ENTRIES {
+            let key = CacheKey {
+                miner_id: [(i / 256) as u8; 32],
+                ..key(i as u8)
+            };
+            cache.insert(key, i as u64);
+        }
+        assert_eq!(1, cache.pows.lock().unwrap().len());
+        assert_eq!(
+            1,
+            FileCache::open(&path).unwrap().pows.lock().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn file_cache_recovers_from_corrupted_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("k2pow_cache.json");
+        {
+            let cache = FileCache::open(&path).unwrap();
+            cache.insert(key(0), 7);
+            cache.insert(key(1), 8);
+        }
+        // A line torn by a crash while appending and some garbage
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str("not a json\n{\"challenge\":\"0101");
+        std::fs::write(&path, content).unwrap();
+
+        let cache = FileCache::open(&path).unwrap();
+        assert_eq!(Some(7), cache.get(&key(0)));
+        assert_eq!(Some(8), cache.get(&key(1)));
+        // Rewritten with the valid entries only
+        assert_eq!(2, std::fs::read_to_string(&path).unwrap().lines().count());
+    }
+
+    #[test]
+    fn cached_prover_calculates_pow_once() {
+        let mut inner = MockProver::new();
+        inner.expect_prove().once().returning(|_, _, _, _| Ok(7));
+
+        let cache = InMemoryCache::default();
+        let prover = CachedProver::new(&inner, Some(&cache));
+        for _ in 0..2 {
+            assert_eq!(7, prover.prove(0, &[1; 8], &[2; 32], &[3; 32]).unwrap());
+        }
+        assert_eq!(Some(7), cache.get(&key(0)));
+    }
+
+    #[test]
+    fn cached_prover_without_cache() {
+        let mut inner = MockProver::new();
+        inner.expect_prove().times(2).returning(|_, _, _, _| Ok(7));
+
+        let prover = CachedProver::new(&inner, None);
+        for _ in 0..2 {
+            assert_eq!(7, prover.prove(0, &[1; 8], &[2; 32], &[3; 32]).unwrap());
+        }
+    }
+}
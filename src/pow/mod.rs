@@ -6,6 +6,7 @@
 //! at the same time. In effect a proof could be found
 //! without actually holding the whole POST data.
 
+pub mod cache;
 pub mod randomx;
 use mockall::*;
 use thiserror::Error;
@@ -10,28 +10,33 @@
 
 use std::borrow::{Borrow, Cow};
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Mutex,
 };
-use std::{collections::HashMap, ops::Range, path::Path, time::Instant};
+use std::{collections::BTreeMap, ops::Range, path::Path, time::Instant};
 
 use aes::cipher::block_padding::NoPadding;
 use aes::cipher::BlockEncrypt;
 use eyre::Context;
 use primitive_types::U256;
-use randomx_rs::RandomXFlag;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
 
 use crate::{
+    checkpoint::{self, Checkpoint, CheckpointedPoW, Checkpointer, PassProgress},
     cipher::AesCipher,
     compression::{compress_indices, required_bits},
     config::ProofConfig,
     difficulty::proving_difficulty,
+    layout,
     metadata::{self, PostMetadata},
-    pow,
-    reader::read_data,
+    pow::{
+        self,
+        cache::{CachedProver, PowCache},
+    },
+    reader::{read_concurrently, PostDataSource, ReadSettings},
+    throughput,
 };
 
 const LABEL_SIZE: usize = 16;
@@ -39,6 +44,9 @@ const BLOCK_SIZE: usize = 16; // size of the aes block
 const AES_BATCH: usize = 8; // will use encrypt8 asm method
 const CHUNK_SIZE: usize = BLOCK_SIZE * AES_BATCH;
 
+/// Maximal number of nonces (k2pow can be calculated for 256 nonce groups).
+pub const MAX_NONCES: u32 = (u8::MAX as u32 + 1) * Prover8_56::NONCES_PER_AES;
+
 #[serde_as]
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Proof<'a> {
@@ -105,6 +113,8 @@ fn nonce_group_range(nonces: Range<u32>, per_aes: u32) -> Range<u32> {
 
 #[derive(Debug)]
 pub struct Prover8_56 {
+    /// The first nonce, the ciphers are indexed relative to it.
+    first_nonce: u32,
     ciphers: Vec<AesCipher>,
     lazy_ciphers: Vec<AesCipher>,
     difficulty_msb: u8,
@@ -112,7 +122,7 @@ pub struct Prover8_56 {
 }
 
 impl Prover8_56 {
-    pub(crate) const NONCES_PER_AES: u32 = 16;
+    pub const NONCES_PER_AES: u32 = 16;
 
     pub fn new<P: pow::Prover>(
         challenge: &[u8; 32],
@@ -146,20 +156,18 @@ impl Prover8_56 {
             })
             .collect::<eyre::Result<_>>()?;
 
+        let first_nonce = nonces.start;
         let lazy_ciphers = nonces
             .map(|nonce| {
                 let nonce_group = calc_nonce_group(nonce, Self::NONCES_PER_AES);
-                AesCipher::new_lazy(
-                    challenge,
-                    nonce,
-                    nonce_group as u32,
-                    ciphers[nonce_group % ciphers.len()].pow,
-                )
+                let offset = calc_nonce_group(nonce - first_nonce, Self::NONCES_PER_AES);
+                AesCipher::new_lazy(challenge, nonce, nonce_group as u32, ciphers[offset].pow)
             })
             .collect();
 
         let (difficulty_msb, difficulty_lsb) = Self::split_difficulty(params.difficulty);
         Ok(Self {
+            first_nonce,
             ciphers,
             lazy_ciphers,
             difficulty_msb,
@@ -173,14 +181,15 @@ impl Prover8_56 {
 
     #[inline(always)]
     fn cipher(&self, nonce: u32) -> Option<&AesCipher> {
+        let offset = nonce.checked_sub(self.first_nonce)?;
         self.ciphers
-            .get(calc_nonce_group(nonce, Self::NONCES_PER_AES) % self.ciphers.len())
+            .get(calc_nonce_group(offset, Self::NONCES_PER_AES))
     }
 
     #[inline(always)]
     fn lazy_cipher(&self, nonce: u32) -> Option<&AesCipher> {
-        self.lazy_ciphers
-            .get(nonce as usize % self.lazy_ciphers.len())
+        let offset = nonce.checked_sub(self.first_nonce)?;
+        self.lazy_ciphers.get(offset as usize)
     }
 
     /// LSB part of the difficulty is checked with second sequence of AES ciphers.
@@ -263,96 +272,485 @@ impl Prover for Prover8_56 {
     }
 }
 
+/// Observer of the proof generation progress.
+///
+/// All events have empty default implementations,
+/// implement only the ones you are interested in.
+pub trait ProgressReporter {
+    /// The number of nonces per pass was picked automatically.
+    fn nonces_picked(&self, _nonces: usize) {}
+    /// A new pass over POS data started (passes are numbered from 0).
+    ///
+    /// If several passes share a read of POS data (see [ProvingOptions::max_passes_per_read]),
+    /// it's reported once for all of them: `pass` is the first one
+    /// and `nonces` spans the nonces of all of them.
+    fn new_pass(&self, _pass: usize, _nonces: Range<u32>) {}
+    /// Started calculating k2pow for the nonce group.
+    fn k2pow_started(&self, _nonce_group: u32) {}
+    /// Finished calculating k2pow for the nonce group.
+    fn k2pow_finished(&self, _nonce_group: u32, _pow: u64) {}
+    /// `read` out of `total` bytes of POS data were processed in the current pass.
+    fn read_progress(&self, _read: u64, _total: u64) {}
+    /// Proof generation finished (successfully or not).
+    fn finished(&self, _result: Result<&Proof<'static>, &eyre::Report>) {}
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopProgressReporter;
+
+impl ProgressReporter for NoopProgressReporter {}
+
+/// PoW prover reporting k2pow progress.
+struct ReportingPoW<'a, P, R: ?Sized> {
+    inner: &'a P,
+    reporter: &'a R,
+}
+
+impl<P: pow::Prover, R: ProgressReporter + ?Sized> pow::Prover for ReportingPoW<'_, P, R> {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, pow::Error> {
+        self.reporter.k2pow_started(nonce_group as u32);
+        let pow = self
+            .inner
+            .prove(nonce_group, challenge, difficulty, miner_id)?;
+        self.reporter.k2pow_finished(nonce_group as u32, pow);
+        Ok(pow)
+    }
+}
+
+/// Number of nonces attempted in a single pass over POS data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nonces {
+    /// A fixed number of nonces (a multiple of 16).
+    Fixed(usize),
+    /// The largest number of nonces that keeps proving I/O-bound, picked by measuring
+    /// throughput of reading and proving the POS data (see [throughput::pick_nonces]).
+    /// When resuming from a checkpoint, the number picked before is reused.
+    Auto,
+}
+
+/// Options of proof generation.
+#[derive(Clone, Copy)]
+pub struct ProvingOptions<'a> {
+    /// Number of nonces attempted in a single pass over POS data.
+    pub nonces: Nonces,
+    /// Maximal number of consecutive passes (windows of nonces) evaluated in a single
+    /// read of POS data. Their k2pow is calculated up front. More passes per read
+    /// trade CPU for I/O, as failed passes don't need to read all the data again.
+    pub max_passes_per_read: usize,
+    /// Number of threads to prove with ('0' means all available).
+    pub threads: usize,
+    /// How the POS data is read (see [ReadSettings]).
+    pub read: ReadSettings,
+    /// Persist the progress in the POS data directory,
+    /// so that proving of the same challenge resumes from it after a restart.
+    pub checkpoint: bool,
+    /// Cache consulted before calculating k2pow for a nonce group.
+    pub pow_cache: Option<&'a dyn PowCache>,
+    /// Notified about the progress as the proof is being generated.
+    pub reporter: &'a (dyn ProgressReporter + Sync),
+}
+
+impl Default for ProvingOptions<'_> {
+    fn default() -> Self {
+        Self {
+            nonces: Nonces::Fixed(128),
+            max_passes_per_read: 1,
+            threads: 0,
+            read: ReadSettings::default(),
+            checkpoint: false,
+            pow_cache: None,
+            reporter: &NoopProgressReporter,
+        }
+    }
+}
+
 /// Generate a proof that data is still held, given the challenge.
-#[allow(clippy::too_many_arguments)]
-pub fn generate_proof<Stopper>(
+///
+/// The k2pow is calculated with `pow_prover`. See [ProvingOptions] for the other settings.
+pub fn generate_proof<PowProver, Stopper>(
     datadir: &Path,
     challenge: &[u8; 32],
     cfg: ProofConfig,
-    nonces: usize,
-    threads: usize,
-    pow_flags: RandomXFlag,
+    pow_prover: &PowProver,
     stop: Stopper,
+    options: ProvingOptions,
 ) -> eyre::Result<Proof<'static>>
 where
+    PowProver: pow::Prover + Sync + ?Sized,
     Stopper: Borrow<AtomicBool>,
 {
-    let stop = stop.borrow();
+    let source = layout::open_source(datadir)?;
+    generate_proof_from_source(
+        datadir,
+        source.as_ref(),
+        challenge,
+        cfg,
+        pow_prover,
+        stop,
+        options,
+    )
+}
+
+/// Generate a proof reading the POS data held by `source`.
+///
+/// The metadata (and the checkpoint) are kept in `datadir`.
+/// See [generate_proof] for the other arguments.
+pub fn generate_proof_from_source<PowProver, Stopper>(
+    datadir: &Path,
+    source: &dyn PostDataSource,
+    challenge: &[u8; 32],
+    cfg: ProofConfig,
+    pow_prover: &PowProver,
+    stop: Stopper,
+    options: ProvingOptions,
+) -> eyre::Result<Proof<'static>>
+where
+    PowProver: pow::Prover + Sync + ?Sized,
+    Stopper: Borrow<AtomicBool>,
+{
+    let result = find_proof(
+        datadir,
+        source,
+        challenge,
+        cfg,
+        pow_prover,
+        stop.borrow(),
+        options,
+    );
+    options.reporter.finished(result.as_ref());
+    result
+}
+
+fn find_proof<PowProver>(
+    datadir: &Path,
+    source: &dyn PostDataSource,
+    challenge: &[u8; 32],
+    cfg: ProofConfig,
+    pow_prover: &PowProver,
+    stop: &AtomicBool,
+    options: ProvingOptions,
+) -> eyre::Result<Proof<'static>>
+where
+    PowProver: pow::Prover + Sync + ?Sized,
+{
+    let ProvingOptions {
+        nonces,
+        max_passes_per_read,
+        threads,
+        read,
+        checkpoint,
+        pow_cache,
+        reporter,
+    } = options;
     let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
     let params = ProvingParams::new(&metadata, &cfg)?;
-    log::info!("generating proof with PoW flags: {pow_flags:?} and params: {params:?}");
-    let pow_prover = pow::randomx::PoW::new(pow_flags)?;
-
-    let mut start_nonce = 0;
-    let mut end_nonce = start_nonce + nonces as u32;
+    eyre::ensure!(
+        read.batch_size % CHUNK_SIZE == 0,
+        "read batch size must be a multiple of {CHUNK_SIZE} bytes"
+    );
+    eyre::ensure!(
+        max_passes_per_read > 0,
+        "max passes per read must be positive"
+    );
+    log::info!("generating proof with params: {params:?}");
+    let pow_prover = CachedProver::new(pow_prover, pow_cache);
 
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(threads)
         .build()
         .wrap_err("building thread pool")?;
 
+    // Where to persist the progress (if enabled)
+    let persist = checkpoint.then_some(datadir);
+    let resumed = persist.and_then(|datadir| checkpoint::resume(datadir, challenge, &params));
+    let nonces = match (nonces, &resumed) {
+        (Nonces::Fixed(n), _) => n as u32,
+        (Nonces::Auto, Some(resumed)) => {
+            let nonces = resumed.nonces_per_pass();
+            log::info!("using {nonces} nonces per pass picked before the restart");
+            nonces
+        }
+        (Nonces::Auto, None) => {
+            let nonces = throughput::pick_nonces(source, read, metadata.max_file_size, &pool)
+                .wrap_err("picking the number of nonces")?;
+            reporter.nonces_picked(nonces);
+            nonces as u32
+        }
+    };
+    eyre::ensure!(nonces > 0, "number of nonces must be positive");
+    // The passes of the checkpoint are aligned to its number of nonces
+    let resumed = resumed.filter(|resumed| {
+        let same = resumed.nonces_per_pass() == nonces;
+        if !same {
+            log::warn!(
+                "discarding the checkpoint made with {} nonces per pass, proving with {nonces}",
+                resumed.nonces_per_pass()
+            );
+        }
+        same
+    });
+    let first_nonces = read_nonces(0, nonces, max_passes_per_read);
+    let checkpoint = resumed.unwrap_or_else(|| Checkpoint::new(*challenge, &params, first_nonces));
+    let checkpointer = Checkpointer::new(persist, checkpoint);
+    let pow_prover = CheckpointedPoW {
+        inner: &pow_prover,
+        checkpointer: &checkpointer,
+    };
+    let pow_prover = ReportingPoW {
+        inner: &pow_prover,
+        reporter,
+    };
+    let (mut start_nonce, mut end_nonce) = {
+        let checkpoint = checkpointer.lock();
+        (checkpoint.nonces.start, checkpoint.nonces.end)
+    };
+    let mut pass = (start_nonce / nonces) as usize;
+    let total_size = metadata.total_size();
+
     let total_time = Instant::now();
     loop {
         if stop.load(Ordering::Relaxed) {
             eyre::bail!("proof generation was stopped");
         }
-
-        let indexes = Mutex::new(HashMap::<u32, Vec<u64>>::new());
+        reporter.new_pass(pass, start_nonce..end_nonce);
+
+        // Each pass has its own prover, all of them are evaluated against every batch
+        let windows = (start_nonce..end_nonce)
+            .step_by(nonces as usize)
+            .map(|start| start..(start + nonces).min(end_nonce))
+            .collect::<Vec<_>>();
+        if windows.len() > 1 {
+            log::info!("evaluating {} passes in a single read", windows.len());
+        }
 
         let pow_time = Instant::now();
-        let prover = pool.install(|| {
-            Prover8_56::new(
-                challenge,
-                start_nonce..end_nonce,
-                params,
-                &pow_prover,
-                &metadata.node_id,
-            )
-            .wrap_err("creating prover")
+        let provers = pool.install(|| {
+            windows
+                .iter()
+                .map(|window| {
+                    Prover8_56::new(
+                        challenge,
+                        window.clone(),
+                        params,
+                        &pow_prover,
+                        &metadata.node_id,
+                    )
+                })
+                .collect::<eyre::Result<Vec<_>>>()
+                .wrap_err("creating prover")
         })?;
 
         let pow_mins = pow_time.elapsed().as_secs() / 60;
         log::info!("Finished k2pow in {} minutes", pow_mins);
 
+        let (start_pos, indexes) = {
+            let checkpoint = checkpointer.lock();
+            (checkpoint.position, checkpoint.indices.clone())
+        };
+        let progress = Mutex::new(PassProgress::new(start_pos, indexes.into_iter().collect()));
+        let bytes_read = AtomicU64::new(start_pos);
+
         let read_time = Instant::now();
-        let data_reader = read_data(datadir, 1024 * 1024, metadata.max_file_size)?;
-        log::info!("Started reading POST data");
-        let result = pool.install(|| {
-            data_reader
-                .par_bridge()
-                .take_any_while(|_| !stop.load(Ordering::Relaxed))
-                .find_map_any(|batch| {
-                    prover.prove(
-                        &batch.data,
-                        batch.pos / BLOCK_SIZE as u64,
-                        |nonce, index| {
-                            let mut indexes = indexes.lock().unwrap();
-                            let vec = indexes.entry(nonce).or_default();
-                            vec.push(index);
-                            if vec.len() >= cfg.k2 as usize {
-                                return Some(std::mem::take(vec));
+        log::info!("Started reading POST data (from position {start_pos})");
+        let result = read_concurrently(
+            source,
+            read,
+            metadata.max_file_size,
+            start_pos..u64::MAX,
+            |batches| {
+                pool.install(|| {
+                    batches
+                        .par_bridge()
+                        .take_any_while(|_| !stop.load(Ordering::Relaxed))
+                        .find_map_any(|batch| {
+                            let result = provers.iter().find_map(|prover| {
+                                prover.prove(
+                                    &batch.data,
+                                    batch.pos / BLOCK_SIZE as u64,
+                                    |nonce, index| {
+                                        let mut progress = progress.lock().unwrap();
+                                        let vec = progress.indexes.entry(nonce).or_default();
+                                        vec.push(index);
+                                        if vec.len() >= cfg.k2 as usize {
+                                            return Some(std::mem::take(vec));
+                                        }
+                                        None
+                                    },
+                                )
+                            });
+                            let len = batch.data.len() as u64;
+                            let read = bytes_read.fetch_add(len, Ordering::Relaxed) + len;
+                            reporter.read_progress(read, total_size);
+                            if result.is_none() && checkpointer.enabled() {
+                                let snapshot = progress.lock().unwrap().batch_done(batch.pos, len);
+                                if let Some((position, indices)) = snapshot {
+                                    checkpointer.advance(position, indices);
+                                }
                             }
-                            None
-                        },
-                    )
+                            result
+                        })
                 })
-        });
+            },
+        )?;
 
         let read_mins = read_time.elapsed().as_secs() / 60;
         log::info!("Finished reading POST data in {} minutes", read_mins);
 
         if let Some((nonce, indices)) = result {
             let num_labels = metadata.num_units as u64 * metadata.labels_per_unit;
-            let pow = prover.get_pow(nonce).unwrap();
+            let pow = windows
+                .iter()
+                .zip(&provers)
+                .find(|(window, _)| window.contains(&nonce))
+                .and_then(|(_, prover)| prover.get_pow(nonce))
+                .unwrap();
 
             let total_minutes = total_time.elapsed().as_secs() / 60;
 
             log::info!("Found proof for nonce: {nonce}, pow: {pow} with {indices:?} indices. Proof took {total_minutes} minutes");
+            checkpointer.finish();
             return Ok(Proof::new(nonce, &indices, num_labels, pow));
         }
 
-        (start_nonce, end_nonce) = (end_nonce, end_nonce + nonces as u32);
+        if stop.load(Ordering::Relaxed) {
+            eyre::bail!("proof generation was stopped");
+        }
+
+        let next = read_nonces(end_nonce, nonces, max_passes_per_read);
+        (start_nonce, end_nonce) = (next.start, next.end);
+        pass += windows.len();
+        checkpointer.start_pass(next);
+    }
+}
+
+/// Nonces of up to `passes` consecutive passes (of `nonces` each) starting at `start`
+/// to evaluate in a single read of POS data.
+///
+/// The passes are limited to the nonce groups k2pow can be calculated for
+/// (the last one is shorter if `nonces` doesn't divide [MAX_NONCES]),
+/// but there is always at least one.
+fn read_nonces(start: u32, nonces: u32, passes: usize) -> Range<u32> {
+    let end = start.saturating_add(nonces.saturating_mul(passes as u32));
+    let limit = if start < MAX_NONCES {
+        MAX_NONCES
+    } else {
+        start + nonces
+    };
+    start..end.min(limit)
+}
+
+/// Indices found by scanning a range of labels (see [prove_range]).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialProof {
+    /// The range of labels scanned
+    pub labels: Range<u64>,
+    /// Indices of labels satisfying the difficulty for each nonce
+    pub indices: BTreeMap<u32, Vec<u64>>,
+}
+
+/// Scan the `labels` of POS data held by `source` with `prover`, collecting indices
+/// of labels satisfying the difficulty for each nonce.
+///
+/// It allows splitting proving of the POS data between several machines,
+/// each scanning its slice. The partial proofs of disjoint ranges are combined
+/// with [merge_partial_proofs].
+///
+/// Scanning stops early once any nonce collects `k2` indices. The range must start
+/// at a multiple of 8 labels and end at one too (unless it's the end of the POS data).
+#[allow(clippy::too_many_arguments)]
+pub fn prove_range(
+    source: &dyn PostDataSource,
+    metadata: &PostMetadata,
+    prover: &Prover8_56,
+    labels: Range<u64>,
+    k2: u32,
+    read: ReadSettings,
+    threads: usize,
+    stop: &AtomicBool,
+) -> eyre::Result<PartialProof> {
+    const LABELS_PER_CHUNK: u64 = (CHUNK_SIZE / LABEL_SIZE) as u64;
+    let total_labels = metadata.total_labels();
+    let labels = labels.start..labels.end.min(total_labels);
+    eyre::ensure!(
+        labels.start % LABELS_PER_CHUNK == 0
+            && (labels.end % LABELS_PER_CHUNK == 0 || labels.end == total_labels),
+        "the range of labels must be aligned to {LABELS_PER_CHUNK} labels"
+    );
+    eyre::ensure!(
+        read.batch_size % CHUNK_SIZE == 0,
+        "read batch size must be a multiple of {CHUNK_SIZE} bytes"
+    );
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .wrap_err("building thread pool")?;
+
+    log::info!("scanning labels {labels:?}");
+    let indices = Mutex::new(BTreeMap::<u32, Vec<u64>>::new());
+    let range = labels.start * LABEL_SIZE as u64..labels.end * LABEL_SIZE as u64;
+    read_concurrently(source, read, metadata.max_file_size, range, |batches| {
+        pool.install(|| {
+            batches
+                .par_bridge()
+                .take_any_while(|_| !stop.load(Ordering::Relaxed))
+                .any(|batch| {
+                    let result = prover.prove(
+                        &batch.data,
+                        batch.pos / BLOCK_SIZE as u64,
+                        |nonce, index| {
+                            let mut indices = indices.lock().unwrap();
+                            let vec = indices.entry(nonce).or_default();
+                            vec.push(index);
+                            (vec.len() >= k2 as usize).then(|| vec.clone())
+                        },
+                    );
+                    result.is_some()
+                })
+        })
+    })?;
+    if stop.load(Ordering::Relaxed) {
+        eyre::bail!("proof generation was stopped");
+    }
+
+    Ok(PartialProof {
+        labels,
+        indices: indices.into_inner().unwrap(),
+    })
+}
+
+/// Merge partial proofs of disjoint ranges of labels (i.e. found by several machines)
+/// into a proof, once any nonce collects at least `k2` indices.
+///
+/// The k2pow of the nonce is taken from `prover`,
+/// `num_labels` is the number of labels in the POS data.
+pub fn merge_partial_proofs<'a>(
+    partials: impl IntoIterator<Item = &'a PartialProof>,
+    prover: &impl Prover,
+    k2: u32,
+    num_labels: u64,
+) -> Option<Proof<'static>> {
+    let mut merged = BTreeMap::<u32, Vec<u64>>::new();
+    for partial in partials {
+        for (&nonce, indices) in &partial.indices {
+            merged.entry(nonce).or_default().extend(indices);
+        }
     }
+    merged.into_iter().find_map(|(nonce, mut indices)| {
+        indices.sort_unstable();
+        indices.dedup();
+        if indices.len() < k2 as usize {
+            return None;
+        }
+        indices.truncate(k2 as usize);
+        let pow = prover.get_pow(nonce)?;
+        Some(Proof::new(nonce, &indices, num_labels, pow))
+    })
 }
 
 #[cfg(test)]
@@ -363,6 +761,16 @@ mod tests {
     use rand::{thread_rng, RngCore};
     use std::{collections::HashMap, iter::repeat};
 
+    #[test]
+    fn nonces_of_passes_sharing_read() {
+        assert_eq!(0..16, read_nonces(0, 16, 1));
+        assert_eq!(32..80, read_nonces(32, 16, 3));
+        // Limited by the number of nonce groups
+        assert_eq!(4064..4096, read_nonces(4064, 16, 4));
+        assert_eq!(4096..4112, read_nonces(4096, 16, 4));
+        assert_eq!(4000..4096, read_nonces(4000, 160, 1));
+    }
+
     #[test]
     fn creating_proof() {
         let indices = vec![0, 1, 2, 3, 4, 5, 6, 7, 8];
@@ -413,6 +821,28 @@ mod tests {
         assert!(Prover8_56::new(&[0; 32], 1..16, params, &pow_prover, &meta.node_id).is_err());
     }
 
+    #[test]
+    fn pows_of_window_not_aligned_to_its_size() {
+        // 6 nonce groups starting at the group 250
+        let mut pow_prover = pow::MockProver::new();
+        pow_prover
+            .expect_prove()
+            .times(6)
+            .returning(|group, _, _, _| Ok(group as u64));
+        let params = ProvingParams {
+            difficulty: u64::MAX,
+            pow_difficulty: [0xFF; 32],
+        };
+        let prover = Prover8_56::new(&[0; 32], 4000..4096, params, &pow_prover, &[0; 32]).unwrap();
+
+        assert_eq!(Some(250), prover.get_pow(4000));
+        assert_eq!(Some(252), prover.get_pow(4040));
+        assert_eq!(Some(255), prover.get_pow(4095));
+        assert_eq!(Some(253), prover.lazy_cipher(4050).map(|aes| aes.pow));
+        assert_eq!(None, prover.get_pow(3999));
+        assert_eq!(None, prover.get_pow(4096));
+    }
+
     #[test]
     fn creating_prover_fails_pow() {
         let meta = PostMetadata {
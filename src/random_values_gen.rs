@@ -1,8 +1,9 @@
+/// Deterministic RNG reading from infinite blake3 output of the seed.
 #[derive(Debug, Clone)]
-struct Blake3Rng(blake3::OutputReader);
+pub(crate) struct Blake3Rng(blake3::OutputReader);
 
 impl Blake3Rng {
-    fn from_seed(seed: &[&[u8]]) -> Self {
+    pub(crate) fn from_seed(seed: &[&[u8]]) -> Self {
         let mut hasher = blake3::Hasher::new();
         for &part in seed {
             hasher.update(part);
@@ -17,6 +18,29 @@ impl Blake3Rng {
     }
 }
 
+impl rand::RngCore for Blake3Rng {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0u8; 4];
+        self.0.fill(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0u8; 8];
+        self.0.fill(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
 /// Picks random items from the provided Vec.
 pub(crate) struct RandomValuesIterator<T> {
     // data shuffled in-place
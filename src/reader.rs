@@ -1,16 +1,24 @@
 use std::{
+    collections::{btree_map::Entry, BTreeMap, VecDeque},
     fs::{DirEntry, File},
-    io::Read,
-    path::Path,
+    io::{Read, Seek, SeekFrom},
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex, OnceLock,
+    },
 };
 
 use eyre::Context;
 use itertools::Itertools;
 use regex::Regex;
 
+use crate::metadata::PostMetadata;
+
 #[derive(Debug, PartialEq, Eq)]
-pub struct Batch {
-    pub data: Vec<u8>,
+pub struct Batch<D = Vec<u8>> {
+    pub data: D,
     pub pos: u64,
 }
 
@@ -104,35 +112,683 @@ pub(crate) fn pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = Dir
     Ok(files)
 }
 
-pub(crate) fn read_data(
-    datadir: &Path,
-    batch_size: usize,
-    file_size: u64,
-) -> eyre::Result<impl Iterator<Item = Batch>> {
-    let mut readers = Vec::<BatchingReader<File>>::new();
-    let mut files = pos_files(datadir)?.enumerate().peekable();
+/// A POS data file exposed by a [PostDataSource].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFile {
+    /// Index of the file in POS data
+    pub idx: usize,
+    /// Name for diagnostics (i.e. "postdata_0.bin")
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Index of the disk holding the file.
+    /// Files on different disks are read concurrently.
+    pub disk: usize,
+}
+
+/// [Read] + [Seek] over a file of a [PostDataSource].
+pub trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Storage backend holding POS data.
+///
+/// POS data is split into files of `max_file_size` bytes (see [PostMetadata](crate::metadata::PostMetadata)).
+/// A source lists the files it holds and reads their contents, regardless of how and where
+/// they are actually stored. The metadata is always kept in the POS data directory.
+pub trait PostDataSource: Send + Sync {
+    /// List the POS data files held, ordered by their index.
+    fn files(&self) -> eyre::Result<Vec<DataFile>>;
+
+    /// Read the file with index `idx` at `pos` (in bytes) into `buf`.
+    ///
+    /// Returns the number of bytes read (less than `buf.len()` only at the end of the file).
+    fn read_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// Read like [read_at](PostDataSource::read_at), bypassing the page cache if possible
+    /// (O_DIRECT on Linux).
+    ///
+    /// It's the fastest when `pos`, the length and the address of `buf` are aligned
+    /// to [DIRECT_IO_ALIGNMENT]. Sources that can't bypass the cache read through it.
+    fn read_direct_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_at(idx, pos, buf)
+    }
+
+    /// Open the file with index `idx` for reading.
+    ///
+    /// Sources that can do better than reading with [read_at](PostDataSource::read_at)
+    /// (i.e. keep a file handle open) should override it.
+    fn open(&self, idx: usize) -> std::io::Result<Box<dyn ReadSeek + '_>> {
+        Ok(Box::new(SourceReader {
+            source: self,
+            idx,
+            pos: 0,
+        }))
+    }
+}
+
+/// Reader of a file of a [PostDataSource] implemented with [read_at](PostDataSource::read_at).
+struct SourceReader<'a, S: ?Sized> {
+    source: &'a S,
+    idx: usize,
+    pos: u64,
+}
+
+impl<S: PostDataSource + ?Sized> Read for SourceReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.source.read_at(self.idx, self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: PostDataSource + ?Sized> Seek for SourceReader<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "seeking from the end is not supported",
+                ))
+            }
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek position")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// POS data stored as `postdata_N.bin` files in a local directory.
+#[derive(Debug, Clone)]
+pub struct Directory {
+    path: PathBuf,
+    /// Files opened for reading, kept open between reads
+    handles: Arc<Mutex<BTreeMap<usize, Arc<OpenFile>>>>,
+}
+
+/// Handles of a file of a [Directory].
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    /// Handle bypassing the page cache, opened on the first direct read
+    direct: OnceLock<Option<File>>,
+}
+
+impl Directory {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.into(),
+            handles: Default::default(),
+        }
+    }
+
+    fn file_path(&self, idx: usize) -> PathBuf {
+        self.path.join(format!("postdata_{idx}.bin"))
+    }
 
-    while let Some((id, entry)) = files.next() {
-        let pos = id as u64 * file_size;
-        let path = entry.path();
-        let file = File::open(&path)?;
-        let pos_file_size = file.metadata().unwrap().len();
+    /// Handles of the file `idx`, opened on the first read.
+    fn handle(&self, idx: usize) -> std::io::Result<Arc<OpenFile>> {
+        match self.handles.lock().unwrap().entry(idx) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                let file = OpenFile {
+                    file: File::open(self.file_path(idx))?,
+                    direct: OnceLock::new(),
+                };
+                Ok(entry.insert(Arc::new(file)).clone())
+            }
+        }
+    }
+}
+
+impl PostDataSource for Directory {
+    fn files(&self) -> eyre::Result<Vec<DataFile>> {
+        pos_files(&self.path)?
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let idx = name["postdata_".len()..name.len() - ".bin".len()].parse()?;
+                let size = entry
+                    .metadata()
+                    .wrap_err_with(|| format!("reading metadata of {name}"))?
+                    .len();
+                Ok(DataFile {
+                    idx,
+                    name,
+                    size,
+                    disk: 0,
+                })
+            })
+            .collect()
+    }
+
+    fn read_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        read_file_at(&self.handle(idx)?.file, buf, pos)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn read_direct_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let handle = self.handle(idx)?;
+        let direct = handle.direct.get_or_init(|| {
+            let path = self.file_path(idx);
+            open_direct(&path).unwrap_or_else(|e| {
+                log::warn!("opening {} for direct I/O failed: {e}", path.display());
+                None
+            })
+        });
+        match direct {
+            Some(file) => read_direct(file, buf, pos),
+            None => read_file_at(&handle.file, buf, pos),
+        }
+    }
+
+    fn open(&self, idx: usize) -> std::io::Result<Box<dyn ReadSeek + '_>> {
+        Ok(Box::new(File::open(self.file_path(idx))?))
+    }
+}
+
+/// POS data stored contiguously in a single file or a block device, starting at an offset.
+///
+/// It's exposed as files of `max_file_size` bytes (as if they were concatenated).
+#[derive(Debug)]
+pub struct SingleFile {
+    file: File,
+    path: PathBuf,
+    /// Handle bypassing the page cache, opened on the first direct read
+    direct: OnceLock<Option<File>>,
+    name: String,
+    offset: u64,
+    max_file_size: u64,
+    total_size: u64,
+}
+
+impl SingleFile {
+    /// Open POS data described by `metadata` stored in `path` at `offset` (in bytes).
+    pub fn open(path: &Path, offset: u64, metadata: &PostMetadata) -> std::io::Result<Self> {
+        Ok(Self::new(
+            File::open(path)?,
+            path,
+            offset,
+            metadata.max_file_size,
+            metadata.total_size(),
+        ))
+    }
+
+    /// Use `file` (opened from `path`) holding `total_size` bytes of POS data at `offset`,
+    /// exposed as files of `max_file_size` bytes.
+    pub fn new(file: File, path: &Path, offset: u64, max_file_size: u64, total_size: u64) -> Self {
+        Self {
+            file,
+            path: path.into(),
+            direct: OnceLock::new(),
+            name: path.display().to_string(),
+            offset,
+            max_file_size,
+            total_size,
+        }
+    }
+
+    /// Size of the (virtual) file `idx`.
+    fn file_size(&self, idx: usize) -> u64 {
+        let start = idx as u64 * self.max_file_size;
+        self.max_file_size
+            .min(self.total_size.saturating_sub(start))
+    }
+}
+
+impl PostDataSource for SingleFile {
+    fn files(&self) -> eyre::Result<Vec<DataFile>> {
+        // The size of a block device isn't in its metadata
+        let len = (&self.file).seek(SeekFrom::End(0))?;
+        let available = len.saturating_sub(self.offset).min(self.total_size);
+        Ok((0..)
+            .map(|idx| (idx, idx as u64 * self.max_file_size))
+            .take_while(|(_, start)| *start < available)
+            .map(|(idx, start)| DataFile {
+                idx,
+                name: format!("{}[{idx}]", self.name),
+                size: self.max_file_size.min(available - start),
+                disk: 0,
+            })
+            .collect())
+    }
 
+    fn read_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_with(read_file_at, &self.file, idx, pos, buf)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn read_direct_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let direct = self.direct.get_or_init(|| {
+            open_direct(&self.path).unwrap_or_else(|e| {
+                log::warn!("opening {} for direct I/O failed: {e}", self.name);
+                None
+            })
+        });
+        match direct {
+            Some(file) => self.read_with(read_direct, file, idx, pos, buf),
+            None => self.read_at(idx, pos, buf),
+        }
+    }
+}
+
+impl SingleFile {
+    /// Read the (virtual) file `idx` from `file` with `read`.
+    fn read_with(
+        &self,
+        read: fn(&File, &mut [u8], u64) -> std::io::Result<usize>,
+        file: &File,
+        idx: usize,
+        pos: u64,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        let len = self
+            .file_size(idx)
+            .saturating_sub(pos)
+            .min(buf.len() as u64) as usize;
+        let pos = self.offset + idx as u64 * self.max_file_size + pos;
+        let mut total = 0;
+        while total < len {
+            match read(file, &mut buf[total..len], pos + total as u64)? {
+                0 => break,
+                n => total += n,
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(unix)]
+fn read_file_at(file: &File, buf: &mut [u8], pos: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, pos)
+}
+
+#[cfg(windows)]
+fn read_file_at(file: &File, buf: &mut [u8], pos: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, pos)
+}
+
+/// Alignment of positions, lengths and buffers of reads bypassing the page cache.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Open `path` for reading bypassing the page cache.
+///
+/// Returns `None` if it's not supported (i.e. by the filesystem).
+#[cfg(target_os = "linux")]
+pub fn open_direct(path: &Path) -> std::io::Result<Option<File>> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            static WARN: std::sync::Once = std::sync::Once::new();
+            WARN.call_once(|| {
+                log::warn!(
+                    "direct I/O is not supported for {}, reading through the page cache",
+                    path.display()
+                )
+            });
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Open `path` for reading bypassing the page cache.
+///
+/// Returns `None` if it's not supported (i.e. by the filesystem).
+#[cfg(not(target_os = "linux"))]
+pub fn open_direct(_path: &Path) -> std::io::Result<Option<File>> {
+    Ok(None)
+}
+
+/// Read from `file` opened with [open_direct].
+///
+/// Unaligned reads go through an aligned bounce buffer (reused by the reading thread).
+#[cfg(target_os = "linux")]
+fn read_direct(file: &File, buf: &mut [u8], pos: u64) -> std::io::Result<usize> {
+    const ALIGN: usize = DIRECT_IO_ALIGNMENT;
+    thread_local! {
+        static BOUNCE: std::cell::RefCell<Vec<u8>> = const { std::cell::RefCell::new(Vec::new()) };
+    }
+    if pos % ALIGN as u64 == 0 && buf.len() % ALIGN == 0 && buf.as_ptr() as usize % ALIGN == 0 {
+        return read_file_at(file, buf, pos);
+    }
+
+    let start = pos - pos % ALIGN as u64;
+    let skip = (pos - start) as usize;
+    let len = (skip + buf.len()).div_ceil(ALIGN) * ALIGN;
+    BOUNCE.with_borrow_mut(|bounce| {
+        if bounce.len() < len + ALIGN {
+            bounce.resize(len + ALIGN, 0);
+        }
+        let offset = bounce.as_ptr().align_offset(ALIGN);
+        let bounce = &mut bounce[offset..offset + len];
+
+        let read = read_file_at(file, bounce, start)?;
+        let read = read.saturating_sub(skip).min(buf.len());
+        buf[..read].copy_from_slice(&bounce[skip..skip + read]);
+        Ok(read)
+    })
+}
+
+/// POS data files spread over several directories (i.e. on separate disks).
+///
+/// Each directory holds a range of the files and is treated as a separate disk.
+#[derive(Debug, Clone)]
+pub struct Striped {
+    stripes: Vec<(Range<usize>, Directory)>,
+}
+
+impl Striped {
+    /// Create a source of files in `stripes` (directories and the indices of files they hold).
+    pub fn new<'a>(stripes: impl IntoIterator<Item = (&'a Path, Range<usize>)>) -> Self {
+        Self {
+            stripes: stripes
+                .into_iter()
+                .map(|(path, files)| (files, Directory::new(path)))
+                .collect(),
+        }
+    }
+
+    fn directory(&self, idx: usize) -> std::io::Result<&Directory> {
+        self.stripes
+            .iter()
+            .find(|(files, _)| files.contains(&idx))
+            .map(|(_, dir)| dir)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no directory holds POS data file {idx}"),
+                )
+            })
+    }
+}
+
+impl PostDataSource for Striped {
+    fn files(&self) -> eyre::Result<Vec<DataFile>> {
+        let mut files = Vec::new();
+        for (disk, (range, dir)) in self.stripes.iter().enumerate() {
+            for file in dir.files()? {
+                if range.contains(&file.idx) {
+                    files.push(DataFile {
+                        name: format!("{}", dir.file_path(file.idx).display()),
+                        disk,
+                        ..file
+                    });
+                }
+            }
+        }
+        files.sort_by_key(|file| file.idx);
+        Ok(files)
+    }
+
+    fn read_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.directory(idx)?.read_at(idx, pos, buf)
+    }
+
+    fn read_direct_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.directory(idx)?.read_direct_at(idx, pos, buf)
+    }
+
+    fn open(&self, idx: usize) -> std::io::Result<Box<dyn ReadSeek + '_>> {
+        self.directory(idx)?.open(idx)
+    }
+}
+
+/// Settings of reading POS data while proving.
+///
+/// With more than one concurrent read, batches are processed out of order,
+/// so proving the same challenge again might find a different (valid) proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadSettings {
+    /// Size of a single read (in bytes)
+    pub batch_size: usize,
+    /// Number of reads issued concurrently on each disk
+    pub queue_depth: usize,
+    /// Bypass the page cache (O_DIRECT on Linux), so that reading POS data doesn't evict
+    /// everything else from memory. The batch size should be a multiple of
+    /// [DIRECT_IO_ALIGNMENT]. Falls back to reading through the cache if unsupported.
+    pub direct_io: bool,
+}
+
+impl Default for ReadSettings {
+    fn default() -> Self {
+        Self {
+            batch_size: 1024 * 1024,
+            queue_depth: 4,
+            direct_io: false,
+        }
+    }
+}
+
+/// Pool of reusable buffers of the same size, aligned to [DIRECT_IO_ALIGNMENT].
+pub(crate) struct BufferPool {
+    size: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            free: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Take a buffer from the pool (allocating a new one if none is free).
+    pub fn get(&self) -> Buffer<'_> {
+        let data = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0; self.size + DIRECT_IO_ALIGNMENT]);
+        Buffer {
+            start: data.as_ptr().align_offset(DIRECT_IO_ALIGNMENT),
+            len: self.size,
+            data,
+            pool: self,
+        }
+    }
+}
+
+/// A buffer taken from a [BufferPool]. It's given back to the pool when dropped.
+pub struct Buffer<'a> {
+    data: Vec<u8>,
+    /// Offset of the aligned part of `data`
+    start: usize,
+    len: usize,
+    pool: &'a BufferPool,
+}
+
+impl Deref for Buffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.start..self.start + self.len]
+    }
+}
+
+impl Buffer<'_> {
+    /// Space to read `len` bytes into.
+    fn space(&mut self, len: usize) -> &mut [u8] {
+        &mut self.data[self.start..self.start + len]
+    }
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        let data = std::mem::take(&mut self.data);
+        self.pool.free.lock().unwrap().push(data);
+    }
+}
+
+/// A region of a POS data file to read.
+struct Region {
+    idx: usize,
+    /// Name of the file, if it's the first region read from it
+    name: Option<String>,
+    /// Offset in the file
+    offset: u64,
+    /// Position in POS data
+    pos: u64,
+    len: usize,
+}
+
+/// Batches read by [read_concurrently], in no particular order.
+pub struct Batches<'a>(Receiver<Batch<Buffer<'a>>>);
+
+impl<'a> Iterator for Batches<'a> {
+    type Item = Batch<Buffer<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.recv().ok()
+    }
+}
+
+/// Read the `range` of POS data (in bytes) held by `source` concurrently.
+///
+/// The files are split into regions of the batch size, which are read
+/// by `settings.queue_depth` threads on each disk (see [DataFile::disk]).
+/// The batches are passed to `consume` as soon as they are read and their buffers
+/// are reused once dropped. Reading stops early if `consume` returns before
+/// reaching the end.
+///
+/// The start of the range should be aligned to the batch size to get the same batches
+/// as if reading from the beginning.
+pub fn read_concurrently<T>(
+    source: &dyn PostDataSource,
+    settings: ReadSettings,
+    file_size: u64,
+    range: Range<u64>,
+    consume: impl FnOnce(Batches<'_>) -> T,
+) -> eyre::Result<T> {
+    eyre::ensure!(settings.batch_size > 0, "batch size must be positive");
+    eyre::ensure!(settings.queue_depth > 0, "queue depth must be positive");
+
+    let mut disks = BTreeMap::<usize, VecDeque<_>>::new();
+    let mut files = source.files()?.into_iter().peekable();
+    let mut expected_idx = 0;
+    while let Some(data_file) = files.next() {
+        if data_file.idx != expected_idx {
+            log::warn!(
+                "missing POS data files {expected_idx}..{}, their labels are skipped",
+                data_file.idx
+            );
+        }
+        expected_idx = data_file.idx + 1;
+        let pos = data_file.idx as u64 * file_size;
+        if pos + file_size <= range.start {
+            continue;
+        }
+        if pos >= range.end {
+            break;
+        }
         // If there are more files, check if the size of the file is correct
-        if files.peek().is_some() && pos_file_size != file_size {
+        if files.peek().is_some() && data_file.size != file_size {
             log::warn!(
-                "invalid POS file {}, expected size: {file_size} vs actual size: {pos_file_size}",
-                path.display(),
+                "invalid POS file {}, expected size: {file_size} vs actual size: {}",
+                data_file.name,
+                data_file.size,
             );
         }
 
-        let identifier = Some(entry.file_name().to_string_lossy().into_owned());
-        readers.push(BatchingReader::new(
-            file, pos, batch_size, file_size, identifier,
-        ));
+        let regions = disks.entry(data_file.disk).or_default();
+        let end = data_file.size.min(file_size).min(range.end - pos);
+        let mut name = Some(data_file.name);
+        let mut offset = range.start.saturating_sub(pos);
+        while offset < end {
+            let len = (end - offset).min(settings.batch_size as u64) as usize;
+            regions.push_back(Region {
+                idx: data_file.idx,
+                name: name.take(),
+                offset,
+                pos: pos + offset,
+                len,
+            });
+            offset += len as u64;
+        }
     }
+    log::info!(
+        "reading POS data from {} disk(s) with {} concurrent reads each",
+        disks.len(),
+        settings.queue_depth
+    );
+
+    let pool = BufferPool::new(settings.batch_size);
+    let queues = disks.into_values().map(Mutex::new).collect::<Vec<_>>();
+    let (tx, rx) = sync_channel(settings.queue_depth * queues.len());
 
-    Ok(readers.into_iter().flatten())
+    std::thread::scope(|scope| {
+        let pool = &pool;
+        let mut readers = Vec::new();
+        for queue in &queues {
+            for _ in 0..settings.queue_depth {
+                let tx = tx.clone();
+                readers.push(
+                    scope.spawn(move || read_regions(source, queue, pool, settings.direct_io, tx)),
+                );
+            }
+        }
+        drop(tx);
+
+        let result = consume(Batches(rx));
+        for reader in readers {
+            reader.join().expect("reader thread panicked")?;
+        }
+        Ok(result)
+    })
+}
+
+fn read_regions<'a>(
+    source: &dyn PostDataSource,
+    regions: &Mutex<VecDeque<Region>>,
+    pool: &'a BufferPool,
+    direct_io: bool,
+    batches: SyncSender<Batch<Buffer<'a>>>,
+) -> eyre::Result<()> {
+    loop {
+        let Some(region) = regions.lock().unwrap().pop_front() else {
+            return Ok(());
+        };
+        if let Some(name) = &region.name {
+            log::info!("Reading file: {name}");
+        }
+        let mut buffer = pool.get();
+        let space = buffer.space(region.len);
+        let mut read = 0;
+        while read < region.len {
+            let pos = region.offset + read as u64;
+            let n = if direct_io {
+                source.read_direct_at(region.idx, pos, &mut space[read..])
+            } else {
+                source.read_at(region.idx, pos, &mut space[read..])
+            }
+            .wrap_err_with(|| format!("reading POS data file {}", region.idx))?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        if read == 0 {
+            continue;
+        }
+        buffer.len = read;
+        let batch = Batch {
+            data: buffer,
+            pos: region.pos,
+        };
+        if batches.send(batch).is_err() {
+            // The consumer is done
+            return Ok(());
+        }
+    }
 }
 
 pub fn read_from<R: Read>(
@@ -146,12 +802,45 @@ pub fn read_from<R: Read>(
 
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
+    use std::io::{Read, Seek, SeekFrom, Write};
     use std::{fs::File, io::Cursor};
 
     use tempfile::tempdir;
 
-    use super::{pos_files, read_data, Batch, BatchingReader};
+    use super::{
+        pos_files, read_concurrently, Batch, BatchingReader, BufferPool, DataFile, Directory,
+        PostDataSource, ReadSettings, SingleFile, Striped,
+    };
+
+    /// Read all POS data from `source` (sorted by position).
+    fn read_data(
+        source: &dyn PostDataSource,
+        batch_size: usize,
+        file_size: u64,
+        start_pos: u64,
+    ) -> eyre::Result<impl Iterator<Item = Batch>> {
+        let settings = ReadSettings {
+            batch_size,
+            queue_depth: 3,
+            ..Default::default()
+        };
+        let mut batches = read_concurrently(
+            source,
+            settings,
+            file_size,
+            start_pos..u64::MAX,
+            |batches| {
+                batches
+                    .map(|batch| Batch {
+                        data: batch.data.to_vec(),
+                        pos: batch.pos,
+                    })
+                    .collect::<Vec<_>>()
+            },
+        )?;
+        batches.sort_by_key(|batch| batch.pos);
+        Ok(batches.into_iter())
+    }
 
     #[test]
     fn batching_reader() {
@@ -195,7 +884,14 @@ mod tests {
         let mut result = Vec::new();
         let mut next_expected_index = 0;
         let file_size = 4u64;
-        for batch in read_data(tmp_dir.path(), file_size as usize, file_size).unwrap() {
+        for batch in read_data(
+            &Directory::new(tmp_dir.path()),
+            file_size as usize,
+            file_size,
+            0,
+        )
+        .unwrap()
+        {
             assert_eq!(next_expected_index, batch.pos);
             result.extend(batch.data);
             next_expected_index += file_size;
@@ -204,6 +900,42 @@ mod tests {
         assert_eq!(b"2Hell1Welc", result.as_slice());
     }
 
+    #[test]
+    fn reading_pos_data_from_position() {
+        let tmp_dir = tempdir().unwrap();
+        let data = ["0123", "4567", "89AB", "CD"];
+        for (i, part) in data.iter().enumerate() {
+            let file_path = tmp_dir.path().join(format!("postdata_{i}.bin"));
+            let mut tmp_file = File::create(file_path).unwrap();
+            write!(tmp_file, "{part}").unwrap();
+        }
+
+        let batches = read_data(&Directory::new(tmp_dir.path()), 2, 4, 6)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Batch {
+                    data: b"67".to_vec(),
+                    pos: 6
+                },
+                Batch {
+                    data: b"89".to_vec(),
+                    pos: 8
+                },
+                Batch {
+                    data: b"AB".to_vec(),
+                    pos: 10
+                },
+                Batch {
+                    data: b"CD".to_vec(),
+                    pos: 12
+                },
+            ],
+            batches
+        );
+    }
+
     #[rstest::rstest]
     #[case("other.bin")]
     #[case("_postadata_0.bin")]
@@ -215,7 +947,202 @@ mod tests {
         let mut tmp_file = File::create(file_path).unwrap();
         write!(tmp_file, "some data").unwrap();
 
-        assert!(read_data(tmp_dir.path(), 4, 4).unwrap().next().is_none());
+        assert!(read_data(&Directory::new(tmp_dir.path()), 4, 4, 0)
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    /// POS data files held in memory.
+    struct MemorySource(Vec<Vec<u8>>);
+
+    impl PostDataSource for MemorySource {
+        fn files(&self) -> eyre::Result<Vec<DataFile>> {
+            Ok(self
+                .0
+                .iter()
+                .enumerate()
+                .map(|(idx, data)| DataFile {
+                    idx,
+                    name: format!("memory_{idx}"),
+                    size: data.len() as u64,
+                    disk: 0,
+                })
+                .collect())
+        }
+
+        fn read_at(&self, idx: usize, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+            let data = self.0[idx].get(pos as usize..).unwrap_or_default();
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn reading_pos_data_from_source() {
+        let source = MemorySource(vec![b"0123".to_vec(), b"4567".to_vec(), b"89".to_vec()]);
+        let data = read_data(&source, 3, 4, 2)
+            .unwrap()
+            .flat_map(|batch| batch.data)
+            .collect::<Vec<_>>();
+        assert_eq!(b"23456789", data.as_slice());
+
+        let mut file = source.open(1).unwrap();
+        file.seek(SeekFrom::Current(1)).unwrap();
+        let mut buf = [0u8; 2];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(b"56", &buf);
+        assert!(file.seek(SeekFrom::End(0)).is_err());
+    }
+
+    #[test]
+    fn reading_pos_data_with_missing_file() {
+        let tmp_dir = tempdir().unwrap();
+        std::fs::write(tmp_dir.path().join("postdata_0.bin"), b"0123").unwrap();
+        std::fs::write(tmp_dir.path().join("postdata_2.bin"), b"89AB").unwrap();
+
+        let batches = read_data(&Directory::new(tmp_dir.path()), 4, 4, 0)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Batch {
+                    data: b"0123".to_vec(),
+                    pos: 0
+                },
+                Batch {
+                    data: b"89AB".to_vec(),
+                    pos: 8
+                },
+            ],
+            batches
+        );
+    }
+
+    #[test]
+    fn listing_directory_files() {
+        let tmp_dir = tempdir().unwrap();
+        std::fs::write(tmp_dir.path().join("postdata_0.bin"), b"0123").unwrap();
+        std::fs::write(tmp_dir.path().join("postdata_1.bin"), b"45").unwrap();
+        std::fs::write(tmp_dir.path().join("other.bin"), b"6789").unwrap();
+
+        let source = Directory::new(tmp_dir.path());
+        assert_eq!(
+            vec![
+                DataFile {
+                    idx: 0,
+                    name: "postdata_0.bin".into(),
+                    size: 4,
+                    disk: 0,
+                },
+                DataFile {
+                    idx: 1,
+                    name: "postdata_1.bin".into(),
+                    size: 2,
+                    disk: 0,
+                },
+            ],
+            source.files().unwrap()
+        );
+        let mut buf = [0u8; 4];
+        assert_eq!(1, source.read_at(1, 1, &mut buf).unwrap());
+        assert_eq!(b'5', buf[0]);
+
+        // The file is kept open
+        #[cfg(unix)]
+        {
+            std::fs::remove_file(tmp_dir.path().join("postdata_1.bin")).unwrap();
+            assert_eq!(2, source.read_at(1, 0, &mut buf).unwrap());
+            assert_eq!(b"45", &buf[..2]);
+        }
+    }
+
+    #[test]
+    fn reading_striped_pos_data() {
+        let disks = [tempdir().unwrap(), tempdir().unwrap()];
+        let data = ["0123", "4567", "89AB", "CD"];
+        for (i, part) in data.iter().enumerate() {
+            let dir = disks[(i >= 2) as usize].path();
+            std::fs::write(dir.join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+        // A file outside of the range of the directory is ignored
+        std::fs::write(disks[0].path().join("postdata_3.bin"), "XX").unwrap();
+
+        let source = Striped::new([(disks[0].path(), 0..2), (disks[1].path(), 2..4)]);
+        let files = source.files().unwrap();
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            files.iter().map(|f| f.idx).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 0, 1, 1],
+            files.iter().map(|f| f.disk).collect::<Vec<_>>()
+        );
+
+        let data = read_data(&source, 4, 4, 2)
+            .unwrap()
+            .flat_map(|batch| batch.data)
+            .collect::<Vec<_>>();
+        assert_eq!(b"23456789ABCD", data.as_slice());
+
+        let mut buf = [0u8; 2];
+        assert_eq!(2, source.read_at(3, 0, &mut buf).unwrap());
+        assert_eq!(b"CD", &buf);
+        assert!(source.read_at(4, 0, &mut buf).is_err());
+    }
+
+    #[test]
+    fn reading_directly() {
+        let tmp_dir = tempdir().unwrap();
+        let data = (0..20000).map(|i| i as u8).collect::<Vec<_>>();
+        let path = tmp_dir.path().join("postdata_0.bin");
+        std::fs::write(&path, &data).unwrap();
+
+        let source = Directory::new(tmp_dir.path());
+        let mut buf = vec![0; 8192];
+        // Unaligned
+        assert_eq!(
+            5000,
+            source.read_direct_at(0, 100, &mut buf[..5000]).unwrap()
+        );
+        assert_eq!(&data[100..5100], &buf[..5000]);
+        // Past the end
+        assert_eq!(3616, source.read_direct_at(0, 16384, &mut buf).unwrap());
+        assert_eq!(&data[16384..], &buf[..3616]);
+
+        let file = File::open(&path).unwrap();
+        let source = SingleFile::new(file, &path, 1000, 8000, 19000);
+        assert_eq!(3000, source.read_direct_at(2, 0, &mut buf).unwrap());
+        assert_eq!(&data[17000..], &buf[..3000]);
+    }
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BufferPool::new(16);
+        let ptr = {
+            let buffer = pool.get();
+            assert_eq!(16, buffer.len());
+            buffer.as_ptr()
+        };
+        assert_eq!(ptr, pool.get().as_ptr());
+    }
+
+    #[test]
+    fn stop_reading_early() {
+        let source = MemorySource((0..100).map(|_| vec![7; 64]).collect());
+        let settings = ReadSettings {
+            batch_size: 16,
+            queue_depth: 2,
+            ..Default::default()
+        };
+        let read = read_concurrently(&source, settings, 64, 0..u64::MAX, |mut batches| {
+            batches
+                .find(|batch| batch.pos >= 1600)
+                .map(|batch| batch.pos)
+        })
+        .unwrap();
+        assert!(read.is_some());
     }
 
     #[test]
@@ -0,0 +1,256 @@
+//! Repairing corrupted POS data in place.
+//!
+//! The damaged labels are regenerated and written over the corrupted ones.
+//! Afterwards, the patched labels are verified by regenerating them on CPU.
+use std::{
+    fs::OpenOptions,
+    io::{BufReader, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use eyre::Context;
+
+use crate::{
+    config::ScryptParams,
+    initialize::{calc_commitment, Initialize, CHECKPOINT_INTERVAL, LABEL_SIZE},
+    layout,
+    metadata::{self, PostMetadata},
+    pos_verification::{sample_labels, SamplingSeed},
+};
+
+/// Range of damaged labels in a POS data file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedLabels {
+    pub file: usize,
+    /// Labels (relative to the beginning of the file)
+    pub labels: Range<u64>,
+}
+
+impl DamagedLabels {
+    /// A single label at `offset` (in bytes) in the file,
+    /// as reported by [pos_verification](crate::pos_verification).
+    pub fn at_offset(file: usize, offset: u64) -> Self {
+        let label = offset / LABEL_SIZE as u64;
+        Self {
+            file,
+            labels: label..label + 1,
+        }
+    }
+
+    /// All labels in the file.
+    pub fn whole_file(metadata: &PostMetadata, file: usize) -> Self {
+        Self {
+            file,
+            labels: 0..metadata.labels_in_file(file) as u64,
+        }
+    }
+}
+
+/// Regenerate `damaged` labels of POS data in `datadir` with `initializer` and overwrite them.
+///
+/// Afterwards, `verify_fraction` % of the patched labels is verified
+/// by regenerating them with `scrypt` ('0' skips verification).
+pub fn repair(
+    datadir: &Path,
+    initializer: &mut dyn Initialize,
+    damaged: &[DamagedLabels],
+    scrypt: ScryptParams,
+    verify_fraction: f64,
+) -> eyre::Result<()> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+    let labels_per_file = metadata.max_file_size / LABEL_SIZE as u64;
+    let layout = layout::load(datadir)?;
+
+    let mut repaired = Vec::with_capacity(damaged.len());
+    for DamagedLabels { file, labels } in damaged {
+        let labels_in_file = metadata.labels_in_file(*file) as u64;
+        eyre::ensure!(
+            labels.start < labels.end && labels.end <= labels_in_file,
+            "invalid labels {labels:?} to repair in file {file} (it has {labels_in_file} labels)"
+        );
+        let mut labels = labels.clone();
+
+        let mut post_data = match layout.file_path(datadir, *file) {
+            Some(path) => {
+                let mut post_data = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path)
+                    .wrap_err_with(|| format!("opening {}", path.display()))?;
+                if labels.end == labels_in_file {
+                    // Drop anything past the expected end of the file
+                    post_data.set_len(labels_in_file * LABEL_SIZE as u64)?;
+                }
+                // Writing past the end of a truncated file would leave a zero-filled gap,
+                // regenerate the missing labels before the range as well.
+                let labels_present = post_data.metadata()?.len() / LABEL_SIZE as u64;
+                if labels.start > labels_present {
+                    log::info!(
+                        "file {file} ends at label {labels_present}, extending repair of {labels:?}"
+                    );
+                    labels.start = labels_present;
+                }
+                post_data.seek(SeekFrom::Start(labels.start * LABEL_SIZE as u64))?;
+                post_data
+            }
+            None => {
+                layout.open_writer(datadir, metadata.max_file_size, *file as u64, labels.start)?
+            }
+        };
+        log::info!("repairing labels {labels:?} in file {file}");
+
+        let first_label = *file as u64 * labels_per_file;
+        for start in labels.clone().step_by(CHECKPOINT_INTERVAL as usize) {
+            let end = labels.end.min(start + CHECKPOINT_INTERVAL);
+            initializer
+                .initialize_to(
+                    &mut post_data,
+                    &commitment,
+                    first_label + start..first_label + end,
+                    None,
+                )
+                .map_err(|e| eyre::eyre!("initializing labels: {e}"))?;
+        }
+        post_data.sync_data()?;
+        repaired.push(DamagedLabels {
+            file: *file,
+            labels,
+        });
+    }
+
+    if verify_fraction > 0.0 {
+        let seed = SamplingSeed::random();
+        let source = layout.source(datadir, &metadata)?;
+        for DamagedLabels { file, labels } in &repaired {
+            let reader = BufReader::new(source.open(*file)?);
+            let invalid = sample_labels(
+                reader,
+                *file,
+                labels.clone(),
+                verify_fraction,
+                &metadata,
+                scrypt,
+                &seed,
+            )?;
+            eyre::ensure!(
+                invalid.is_empty(),
+                "labels at offsets {invalid:?} in file {file} are still invalid after repair"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::{
+        initialize::CpuInitializer,
+        pos_verification::{check_integrity, verify_files},
+    };
+
+    use super::*;
+
+    #[test]
+    fn repairs_damaged_labels() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+
+        // Corrupt a label in the second file, cut the third short and remove the last one
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(datadir.path().join("postdata_1.bin"))
+            .unwrap();
+        file.seek(SeekFrom::Start(16 * 7)).unwrap();
+        file.write_all(&[0xAB; 16]).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(datadir.path().join("postdata_2.bin"))
+            .unwrap()
+            .set_len(16 * 10 + 3)
+            .unwrap();
+        std::fs::remove_file(datadir.path().join("postdata_5.bin")).unwrap();
+        assert!(verify_files(datadir.path(), 100.0, None, None, scrypt, None).is_err());
+
+        let damaged = [
+            DamagedLabels::at_offset(1, 16 * 7),
+            DamagedLabels::whole_file(&metadata, 2),
+            DamagedLabels::whole_file(&metadata, 5),
+        ];
+        repair(
+            datadir.path(),
+            &mut CpuInitializer::new(scrypt),
+            &damaged,
+            scrypt,
+            100.0,
+        )
+        .unwrap();
+
+        assert!(check_integrity(datadir.path(), 100.0, scrypt, None)
+            .unwrap()
+            .is_valid());
+    }
+
+    #[test]
+    fn repairs_labels_past_end_of_truncated_file() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+
+        let path = datadir.path().join("postdata_0.bin");
+        let original = std::fs::read(&path).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(16 * 10)
+            .unwrap();
+
+        // The labels 10..20 are missing too, they must not be left zeroed
+        let damaged = [DamagedLabels {
+            file: 0,
+            labels: 20..30,
+        }];
+        repair(
+            datadir.path(),
+            &mut CpuInitializer::new(scrypt),
+            &damaged,
+            scrypt,
+            100.0,
+        )
+        .unwrap();
+
+        assert_eq!(&original[..16 * 30], std::fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn rejects_labels_out_of_file() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+
+        let damaged = [DamagedLabels {
+            file: 0,
+            labels: 40..51,
+        }];
+        assert!(repair(
+            datadir.path(),
+            &mut CpuInitializer::new(scrypt),
+            &damaged,
+            scrypt,
+            0.0,
+        )
+        .is_err());
+    }
+}
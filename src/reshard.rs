@@ -0,0 +1,288 @@
+//! Re-sharding POS data to a different maximal file size.
+//!
+//! The labels are copied into new files in a staging directory inside the POS data directory.
+//! The staged files are committed (moved over the original ones) only when all of them are
+//! written and verified, along with the metadata describing them. An interrupted re-sharding
+//! is either started over (if staging wasn't finished) or its commit is completed.
+//!
+//! It requires enough free space to hold a second copy of the POS data.
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+
+use crate::{
+    config::ScryptParams,
+    initialize::LABEL_SIZE,
+    layout::{self, Layout},
+    metadata::{self, PostMetadata},
+    pos_verification,
+    reader::Directory,
+};
+
+/// Name of the staging directory (inside the POS data directory).
+pub const STAGING_DIR_NAME: &str = "postdata_reshard";
+
+fn file_path(dir: &Path, id: usize) -> PathBuf {
+    dir.join(format!("postdata_{id}.bin"))
+}
+
+/// Rewrite POS data in `datadir` into files of `max_file_size` bytes.
+///
+/// Before committing, `verify_fraction` % of the re-sharded labels is verified
+/// by regenerating them with `scrypt` ('0' skips verification).
+pub fn reshard(
+    datadir: &Path,
+    max_file_size: u64,
+    scrypt: ScryptParams,
+    verify_fraction: f64,
+) -> eyre::Result<PostMetadata> {
+    eyre::ensure!(
+        max_file_size > 0 && max_file_size % LABEL_SIZE as u64 == 0,
+        "max file size must be a positive multiple of {LABEL_SIZE}"
+    );
+    let staging = datadir.join(STAGING_DIR_NAME);
+
+    // Finish or discard an interrupted re-sharding
+    if staging.exists() {
+        match metadata::load(&staging) {
+            Ok(staged) => {
+                log::info!("completing interrupted re-sharding");
+                commit(datadir, &staging, &staged)?;
+            }
+            Err(_) => {
+                log::info!("discarding unfinished re-sharding");
+                std::fs::remove_dir_all(&staging)?;
+            }
+        }
+    }
+
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    eyre::ensure!(
+        metadata.last_position.is_none(),
+        "POS data is not fully initialized"
+    );
+    if metadata.max_file_size == max_file_size {
+        log::info!("POS data already uses files of {max_file_size} bytes");
+        return Ok(metadata);
+    }
+
+    let resharded = PostMetadata {
+        max_file_size,
+        ..metadata
+    };
+    match layout::load(datadir)? {
+        Layout::Files => {}
+        Layout::SingleFile { .. } => {
+            // Labels are stored contiguously, the files are only virtual
+            log::info!("using files of {max_file_size} bytes for POS data in a single file");
+            metadata::save(datadir, &resharded)?;
+            return Ok(resharded);
+        }
+        Layout::Striped { .. } => eyre::bail!("re-sharding striped POS data is not supported"),
+    }
+    log::info!(
+        "re-sharding POS data in {} from {} to {} files",
+        datadir.display(),
+        metadata.num_files(),
+        resharded.num_files()
+    );
+
+    std::fs::create_dir_all(&staging)?;
+    copy_labels(datadir, &metadata, &staging, &resharded)?;
+
+    if verify_fraction > 0.0 {
+        let source = Directory::new(&staging);
+        let verified = pos_verification::verify_source(
+            &source,
+            &resharded,
+            verify_fraction,
+            None,
+            None,
+            scrypt,
+            None,
+        );
+        if let Err(e) = verified {
+            std::fs::remove_dir_all(&staging)?;
+            return Err(e).wrap_err("verifying re-sharded POS data");
+        }
+    }
+    // The metadata marks the staged files as complete and verified
+    metadata::save(&staging, &resharded)?;
+
+    commit(datadir, &staging, &resharded)?;
+    Ok(resharded)
+}
+
+/// Stream labels from the files described by `from` into the files described by `to`.
+fn copy_labels(
+    from_dir: &Path,
+    from: &PostMetadata,
+    to_dir: &Path,
+    to: &PostMetadata,
+) -> eyre::Result<()> {
+    let mut output_id = 0;
+    let mut output = File::create(file_path(to_dir, output_id))?;
+    let mut capacity = to.labels_in_file(output_id) as u64 * LABEL_SIZE as u64;
+
+    for input_id in 0..from.num_files() {
+        let path = file_path(from_dir, input_id);
+        let mut input =
+            File::open(&path).wrap_err_with(|| format!("opening {}", path.display()))?;
+        let mut remaining = from.labels_in_file(input_id) as u64 * LABEL_SIZE as u64;
+        let size = input.metadata()?.len();
+        eyre::ensure!(
+            size == remaining,
+            "invalid size of {}: {size} (expected {remaining})",
+            path.display()
+        );
+
+        while remaining > 0 {
+            if capacity == 0 {
+                output.sync_all()?;
+                output_id += 1;
+                output = File::create(file_path(to_dir, output_id))?;
+                capacity = to.labels_in_file(output_id) as u64 * LABEL_SIZE as u64;
+            }
+            let len = remaining.min(capacity);
+            let copied = std::io::copy(&mut (&mut input).take(len), &mut output)?;
+            eyre::ensure!(copied == len, "{} is shorter than expected", path.display());
+            remaining -= len;
+            capacity -= len;
+        }
+    }
+    output.sync_all()?;
+    Ok(())
+}
+
+/// Move the staged files over the original ones and update the metadata.
+///
+/// Every step is idempotent, so an interrupted commit can be simply repeated.
+fn commit(datadir: &Path, staging: &Path, staged: &PostMetadata) -> eyre::Result<()> {
+    let num_files = staged.num_files();
+    for id in 0..num_files {
+        let path = file_path(staging, id);
+        if path.exists() {
+            std::fs::rename(&path, file_path(datadir, id))?;
+        }
+    }
+    // Remove the remaining original files
+    for id in num_files.. {
+        let path = file_path(datadir, id);
+        if !path.exists() {
+            break;
+        }
+        std::fs::remove_file(path)?;
+    }
+    metadata::save(datadir, staged)?;
+    std::fs::remove_dir_all(staging)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{
+        initialize::{tests::read_files, CpuInitializer, Initialize},
+        reader,
+    };
+
+    use super::*;
+
+    #[rstest]
+    #[case::bigger(70)]
+    #[case::smaller(13)]
+    #[case::single_file(1000)]
+    fn reshards_to_new_file_size(#[case] labels_per_file: u64) {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let original = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+        let data = read_files(datadir.path());
+
+        let resharded = reshard(datadir.path(), labels_per_file * 16, scrypt, 100.0).unwrap();
+
+        assert_eq!(labels_per_file * 16, resharded.max_file_size);
+        assert_eq!(original.num_units, resharded.num_units);
+        let loaded = metadata::load(datadir.path()).unwrap();
+        assert_eq!(resharded.max_file_size, loaded.max_file_size);
+        assert_eq!(
+            resharded.num_files(),
+            reader::pos_files(datadir.path()).unwrap().count()
+        );
+        assert_eq!(data, read_files(datadir.path()));
+        assert!(!datadir.path().join(STAGING_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn completes_interrupted_commit() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let original = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+        let data = read_files(datadir.path());
+
+        // Stage the files and move only some of them
+        let staging = datadir.path().join(STAGING_DIR_NAME);
+        std::fs::create_dir_all(&staging).unwrap();
+        let resharded = PostMetadata {
+            max_file_size: 16 * 120,
+            ..original
+        };
+        copy_labels(datadir.path(), &original, &staging, &resharded).unwrap();
+        metadata::save(&staging, &resharded).unwrap();
+        std::fs::rename(file_path(&staging, 0), file_path(datadir.path(), 0)).unwrap();
+
+        let metadata = reshard(datadir.path(), 16 * 120, scrypt, 0.0).unwrap();
+        assert_eq!(16 * 120, metadata.max_file_size);
+        assert_eq!(data, read_files(datadir.path()));
+        assert!(!file_path(datadir.path(), 3).exists());
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn keeps_original_files_if_verification_fails() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let original = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+        // Corrupt a label, so that the re-sharded data fails verification
+        let path = file_path(datadir.path(), 1);
+        let mut content = std::fs::read(&path).unwrap();
+        content[..16].fill(0xAB);
+        std::fs::write(&path, content).unwrap();
+        let data = read_files(datadir.path());
+
+        for _ in 0..2 {
+            assert!(reshard(datadir.path(), 16 * 40, scrypt, 100.0).is_err());
+            assert!(!datadir.path().join(STAGING_DIR_NAME).exists());
+            let loaded = metadata::load(datadir.path()).unwrap();
+            assert_eq!(original.max_file_size, loaded.max_file_size);
+            assert_eq!(data, read_files(datadir.path()));
+        }
+    }
+
+    #[test]
+    fn discards_unfinished_staging() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 100, 3, 50, None)
+            .unwrap();
+        let data = read_files(datadir.path());
+
+        let staging = datadir.path().join(STAGING_DIR_NAME);
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::write(file_path(&staging, 0), [0xAB; 7]).unwrap();
+
+        reshard(datadir.path(), 16 * 40, scrypt, 0.0).unwrap();
+        assert_eq!(data, read_files(datadir.path()));
+    }
+}
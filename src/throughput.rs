@@ -0,0 +1,191 @@
+//! Measuring throughput of reading and proving POS data.
+//!
+//! It's used by the profiler and to pick the number of nonces automatically
+//! (see [pick_nonces]), so that proving stays I/O-bound.
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+use rayon::{
+    prelude::{ParallelBridge, ParallelIterator},
+    ThreadPool,
+};
+
+use crate::{
+    pow,
+    prove::{Prover, Prover8_56, ProvingParams, MAX_NONCES},
+    reader::{read_concurrently, Batch, PostDataSource, ReadSettings},
+};
+
+/// Size of POS data sampled to pick the number of nonces.
+const SAMPLE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Amount of data processed in some time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub bytes: u64,
+    pub time: Duration,
+}
+
+impl Measurement {
+    /// Throughput in bytes per second.
+    pub fn speed(&self) -> f64 {
+        self.bytes as f64 / self.time.as_secs_f64()
+    }
+}
+
+/// PoW prover for measurements (k2pow isn't measured).
+struct NoPoW;
+
+impl pow::Prover for NoPoW {
+    fn prove(&self, _: u8, _: &[u8; 8], _: &[u8; 32], _: &[u8; 32]) -> Result<u64, pow::Error> {
+        Ok(0)
+    }
+}
+
+/// Prover with `nonces` nonces that never finds a proof.
+fn prover(nonces: u32) -> eyre::Result<Prover8_56> {
+    let params = ProvingParams {
+        difficulty: 0, // impossible to find a proof
+        pow_difficulty: [0xFF; 32],
+    };
+    let challenge = b"hello world, challenge me!!!!!!!";
+    Prover8_56::new(challenge, 0..nonces, params, &NoPoW, &[7; 32])
+}
+
+/// Measure proving up to `limit` bytes of POS data held by `source` with `nonces` nonces
+/// in `pool`. With no nonces, only reading is measured.
+pub fn measure_proving(
+    source: &dyn PostDataSource,
+    read: ReadSettings,
+    file_size: u64,
+    limit: u64,
+    nonces: u32,
+    pool: &ThreadPool,
+) -> eyre::Result<Measurement> {
+    let prover = match nonces {
+        0 => None,
+        n => Some(prover(n)?),
+    };
+    let start = Instant::now();
+    let bytes = read_concurrently(source, read, file_size, 0..limit, |batches| {
+        pool.install(|| {
+            batches
+                .par_bridge()
+                .map(|batch| {
+                    if let Some(prover) = &prover {
+                        prover.prove(&batch.data, batch.pos, |_, _| None);
+                    }
+                    batch.data.len() as u64
+                })
+                .sum()
+        })
+    })?;
+    Ok(Measurement {
+        bytes,
+        time: start.elapsed(),
+    })
+}
+
+/// Measure proving `size` bytes of (random) data held in memory with `nonces` nonces in `pool`.
+pub fn measure_aes(size: usize, nonces: u32, pool: &ThreadPool) -> eyre::Result<Measurement> {
+    let prover = prover(nonces)?;
+    let batch_size = ReadSettings::default().batch_size;
+    let mut data = vec![0u8; size];
+    rand::thread_rng().fill_bytes(&mut data);
+
+    let start = Instant::now();
+    pool.install(|| {
+        data.chunks(batch_size)
+            .enumerate()
+            .map(|(i, data)| Batch {
+                data,
+                pos: (i * batch_size) as u64,
+            })
+            .par_bridge()
+            .for_each(|batch| {
+                prover.prove(batch.data, batch.pos, |_, _| None);
+            })
+    });
+    Ok(Measurement {
+        bytes: size as u64,
+        time: start.elapsed(),
+    })
+}
+
+/// Pick the largest number of nonces that keeps proving POS data held by `source` I/O-bound.
+///
+/// Measures reading the beginning of the data and proving it with 16 nonces in `pool`.
+/// The time of proving grows linearly with the number of nonces (in groups of 16).
+pub fn pick_nonces(
+    source: &dyn PostDataSource,
+    read: ReadSettings,
+    file_size: u64,
+    pool: &ThreadPool,
+) -> eyre::Result<usize> {
+    let per_aes = Prover8_56::NONCES_PER_AES;
+    let disk = measure_proving(source, read, file_size, SAMPLE_SIZE, 0, pool)?;
+    let aes = measure_aes(disk.bytes.max(1) as usize, per_aes, pool)?;
+
+    let nonces = nonces_for(disk, aes);
+    log::info!(
+        "picked {nonces} nonces (reading: {:.2} MiB/s, proving {per_aes} nonces: {:.2} MiB/s)",
+        disk.speed() / (1024.0 * 1024.0),
+        aes.speed() / (1024.0 * 1024.0),
+    );
+    Ok(nonces)
+}
+
+/// The number of nonces that can be proved as fast as reading goes,
+/// given the throughput of reading (`disk`) and of proving a single group of nonces (`aes`).
+fn nonces_for(disk: Measurement, aes: Measurement) -> usize {
+    let per_aes = Prover8_56::NONCES_PER_AES as usize;
+    let groups = (aes.speed() / disk.speed()) as usize;
+    groups
+        .saturating_mul(per_aes)
+        .clamp(per_aes, MAX_NONCES as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picking_nonces() {
+        let datadir = tempfile::tempdir().unwrap();
+        std::fs::write(datadir.path().join("postdata_0.bin"), vec![7; 1024 * 1024]).unwrap();
+        let source = crate::reader::Directory::new(datadir.path());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+
+        let read =
+            measure_proving(&source, ReadSettings::default(), 1 << 20, 1 << 20, 0, &pool).unwrap();
+        assert_eq!(1024 * 1024, read.bytes);
+
+        let nonces = pick_nonces(&source, ReadSettings::default(), 1 << 20, &pool).unwrap();
+        assert!(nonces % 16 == 0);
+        assert!((16..=MAX_NONCES as usize).contains(&nonces));
+    }
+
+    #[test]
+    fn nonces_follow_throughput_ratio() {
+        let speed = |mib_per_s: u64| Measurement {
+            bytes: mib_per_s * 1024 * 1024,
+            time: Duration::from_secs(1),
+        };
+        // Proving a group is 5.5x faster than reading, 5 groups keep up
+        assert_eq!(80, nonces_for(speed(200), speed(1100)));
+        assert_eq!(16, nonces_for(speed(200), speed(200)));
+        // At least one group, even if proving is slower than reading
+        assert_eq!(16, nonces_for(speed(1000), speed(100)));
+        // No more than the maximum
+        assert_eq!(MAX_NONCES as usize, nonces_for(speed(1), speed(1_000_000)));
+        // Reading nothing
+        let nothing = Measurement {
+            bytes: 0,
+            time: Duration::from_secs(1),
+        };
+        assert_eq!(MAX_NONCES as usize, nonces_for(nothing, speed(100)));
+    }
+}
@@ -40,11 +40,14 @@ use std::cmp::Ordering;
 use cipher::BlockEncrypt;
 use itertools::Itertools;
 use log::debug;
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
 
 use crate::{
     cipher::AesCipher,
     compression::{decompress_indexes, required_bits},
-    config::{InitConfig, ProofConfig},
+    config::{InitConfig, ProofConfig, ScryptParams},
     difficulty::{proving_difficulty, scale_pow_difficulty},
     initialize::{calc_commitment, generate_label},
     metadata::ProofMetadata,
@@ -57,6 +60,7 @@ const NONCES_PER_AES: u32 = Prover8_56::NONCES_PER_AES;
 
 pub struct Verifier {
     pow_verifier: Box<dyn PowVerifier + Send + Sync>,
+    pool: Option<rayon::ThreadPool>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -115,8 +119,25 @@ pub fn verify_metadata(
 }
 
 impl Verifier {
-    pub fn new(pow_verifier: Box<dyn PowVerifier + Send + Sync>) -> Self {
-        Self { pow_verifier }
+    /// Create a new verifier.
+    ///
+    /// The K3 labels of a proof, and the proofs given to [Verifier::verify_many],
+    /// are verified in parallel in a pool of `threads` threads built once here.
+    /// '1' means verifying them one by one on the calling thread,
+    /// '0' means using all available threads.
+    pub fn new(
+        pow_verifier: Box<dyn PowVerifier + Send + Sync>,
+        threads: usize,
+    ) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let pool = match threads {
+            1 => None,
+            threads => Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()?,
+            ),
+        };
+        Ok(Self { pow_verifier, pool })
     }
 
     /// Verify if a proof is valid.
@@ -169,68 +190,289 @@ impl Verifier {
         let indices_unpacked = decompress_indexes(&proof.indices, bits_per_index)
             .take(cfg.k2 as usize)
             .collect_vec();
-        let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
-        let cipher = AesCipher::new(&challenge, nonce_group, proof.pow);
-        let lazy_cipher = AesCipher::new_lazy(&challenge, proof.nonce, nonce_group, proof.pow);
-
         let difficulty = proving_difficulty(cfg.k1, num_labels).map_err(Error::InvalidNumLabels)?;
-        let (difficulty_msb, difficulty_lsb) = Prover8_56::split_difficulty(difficulty);
+        let labels = LabelVerifier::new(proof, metadata, init_cfg, difficulty);
+        let k3_indices = select_k3_indices(proof, &challenge, indices_unpacked, cfg.k3);
+        let verify_index = |index| labels.verify(&labels.check(index));
 
-        let output_index = (proof.nonce % NONCES_PER_AES) as usize;
+        match &self.pool {
+            // Fails fast, on the first invalid label found
+            Some(pool) => pool.install(|| {
+                k3_indices
+                    .collect_vec()
+                    .into_par_iter()
+                    .try_for_each(verify_index)
+            }),
+            None => k3_indices.into_iter().try_for_each(verify_index),
+        }
+    }
 
-        // Select K3 indices
-        let seed = &[
-            challenge.as_slice(),
-            &proof.nonce.to_le_bytes(),
-            proof.indices.as_ref(),
-            &proof.pow.to_le_bytes(),
-        ];
+    /// Check every step of a proof verification independently and report all problems found.
+    ///
+    /// Unlike [Verifier::verify], it doesn't stop on the first failure and it also
+    /// returns the intermediate values (indices, selected K3 labels, difficulties).
+    /// Useful for debugging why a proof is rejected.
+    pub fn verify_report(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+    ) -> VerificationReport {
+        let mut report = VerificationReport {
+            failures: Vec::new(),
+            pow_difficulty: None,
+            difficulty: None,
+            indices: Vec::new(),
+            k3: Vec::new(),
+        };
+        if let Err(e) = verify_metadata(metadata, init_cfg) {
+            report.failures.push(e.into());
+        }
 
-        let k3_indices = RandomValuesIterator::new(indices_unpacked, seed).take(cfg.k3 as usize);
+        // Without labels, neither the PoW difficulty nor the indices can be calculated.
+        let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
+        if num_labels == 0 {
+            report
+                .failures
+                .push(Error::InvalidNumLabels("there are no labels".into()));
+            return report;
+        }
 
-        k3_indices.into_iter().try_for_each(|index| {
-            let mut output = [0u8; 16];
-            let label = generate_label(&commitment, init_cfg.scrypt, index);
-            cipher
-                .aes
-                .encrypt_block_b2b(&label.into(), (&mut output).into());
+        let challenge = metadata.challenge;
+        let pow_difficulty = scale_pow_difficulty(&cfg.pow_difficulty, metadata.num_units);
+        report.pow_difficulty = Some(pow_difficulty);
 
-            let msb = output[output_index];
-            match msb.cmp(&difficulty_msb) {
-                Ordering::Less => {
-                    // valid
-                }
-                Ordering::Greater => {
-                    return Err(Error::InvalidMsb {
-                        index,
-                        msb,
-                        difficulty_msb,
-                        label,
-                    })
+        let nonce_group = proof.nonce / NONCES_PER_AES;
+        match u8::try_from(nonce_group) {
+            Ok(nonce_group) => {
+                if let Err(e) = self.pow_verifier.verify(
+                    proof.pow,
+                    nonce_group,
+                    &challenge[..8].try_into().unwrap(),
+                    &pow_difficulty,
+                    &metadata.node_id,
+                ) {
+                    report.failures.push(e.into());
                 }
-                Ordering::Equal => {
-                    // Need to check LSB
-                    let mut output = [0u64; 2];
-                    lazy_cipher.aes.encrypt_block_b2b(
-                        &label.into(),
-                        bytemuck::cast_slice_mut(&mut output).into(),
-                    );
-                    let lsb = output[0].to_le() & 0x00ff_ffff_ffff_ffff;
-                    if lsb >= difficulty_lsb {
-                        return Err(Error::InvalidLsb {
-                            index,
-                            lsb,
-                            difficulty_lsb,
-                            label,
-                        });
-                    }
+            }
+            Err(_) => report
+                .failures
+                .push(Error::NonceGroupOutOfBounds(nonce_group)),
+        }
+
+        let bits_per_index = required_bits(num_labels);
+        let expected = expected_indices_bytes(bits_per_index, cfg.k2);
+        if proof.indices.len() != expected {
+            report.failures.push(Error::InvalidIndicesLen {
+                expected,
+                got: proof.indices.len(),
+            });
+        }
+        let indices = decompress_indexes(&proof.indices, bits_per_index)
+            .take(cfg.k2 as usize)
+            .collect_vec();
+        report.indices = indices.clone();
+
+        let difficulty = match proving_difficulty(cfg.k1, num_labels) {
+            Ok(difficulty) => difficulty,
+            Err(e) => {
+                report.failures.push(Error::InvalidNumLabels(e));
+                return report;
+            }
+        };
+        let labels = LabelVerifier::new(proof, metadata, init_cfg, difficulty);
+        report.difficulty = Some(DifficultyReport {
+            difficulty,
+            msb: labels.difficulty_msb,
+            lsb: labels.difficulty_lsb,
+        });
+
+        let k3_indices = select_k3_indices(proof, &challenge, indices, cfg.k3);
+        report.k3 = match &self.pool {
+            Some(pool) => pool.install(|| {
+                k3_indices
+                    .collect_vec()
+                    .into_par_iter()
+                    .map(|index| labels.check(index))
+                    .collect()
+            }),
+            None => k3_indices.map(|index| labels.check(index)).collect(),
+        };
+        report
+            .failures
+            .extend(report.k3.iter().filter_map(|l| labels.verify(l).err()));
+        report
+    }
+
+    /// Verify many proofs in parallel, in the pool of the verifier (see [Verifier::new]).
+    ///
+    /// Returns the result of verification of each proof (in the same order as `proofs`),
+    /// an invalid proof doesn't stop verification of the others.
+    ///
+    /// Arguments:
+    ///
+    /// * `proofs`: The proofs to verify along with their metadata
+    pub fn verify_many(
+        &self,
+        proofs: &[(Proof, ProofMetadata)],
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+    ) -> Vec<Result<(), Error>> {
+        let verify = |(proof, metadata): &(Proof, ProofMetadata)| {
+            self.verify(proof, metadata, cfg, init_cfg)
+        };
+        match &self.pool {
+            Some(pool) => pool.install(|| proofs.par_iter().map(verify).collect()),
+            None => proofs.iter().map(verify).collect(),
+        }
+    }
+}
+
+/// Detailed outcome of checking a proof, see [Verifier::verify_report].
+#[serde_as]
+#[derive(Debug, Serialize)]
+pub struct VerificationReport {
+    /// All problems found in the proof. The proof is valid if there are none.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub failures: Vec<Error>,
+    /// PoW difficulty scaled by the number of units, absent if there are no units.
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    pub pow_difficulty: Option<[u8; 32]>,
+    /// Proving difficulty (inferred from K1), absent if it couldn't be calculated.
+    pub difficulty: Option<DifficultyReport>,
+    /// Decompressed proven indices.
+    pub indices: Vec<u64>,
+    /// The selected subset of K3 labels.
+    pub k3: Vec<LabelReport>,
+}
+
+impl VerificationReport {
+    pub fn is_valid(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DifficultyReport {
+    pub difficulty: u64,
+    pub msb: u8,
+    pub lsb: u64,
+}
+
+/// A regenerated K3 label and the values compared against the difficulty.
+#[serde_as]
+#[derive(Debug, Serialize)]
+pub struct LabelReport {
+    pub index: u64,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub label: [u8; 16],
+    pub msb: u8,
+    /// Only calculated if MSB is equal to difficulty MSB.
+    pub lsb: Option<u64>,
+}
+
+/// Regenerates labels pointed by the proven indices and checks them against the difficulty.
+struct LabelVerifier {
+    commitment: [u8; 32],
+    scrypt: ScryptParams,
+    cipher: AesCipher,
+    lazy_cipher: AesCipher,
+    output_index: usize,
+    difficulty_msb: u8,
+    difficulty_lsb: u64,
+}
+
+impl LabelVerifier {
+    fn new(
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        init_cfg: &InitConfig,
+        difficulty: u64,
+    ) -> Self {
+        let nonce_group = proof.nonce / NONCES_PER_AES;
+        let (difficulty_msb, difficulty_lsb) = Prover8_56::split_difficulty(difficulty);
+        Self {
+            commitment: calc_commitment(&metadata.node_id, &metadata.commitment_atx_id),
+            scrypt: init_cfg.scrypt,
+            cipher: AesCipher::new(&metadata.challenge, nonce_group, proof.pow),
+            lazy_cipher: AesCipher::new_lazy(
+                &metadata.challenge,
+                proof.nonce,
+                nonce_group,
+                proof.pow,
+            ),
+            output_index: (proof.nonce % NONCES_PER_AES) as usize,
+            difficulty_msb,
+            difficulty_lsb,
+        }
+    }
+
+    fn check(&self, index: u64) -> LabelReport {
+        let mut output = [0u8; 16];
+        let label = generate_label(&self.commitment, self.scrypt, index);
+        self.cipher
+            .aes
+            .encrypt_block_b2b(&label.into(), (&mut output).into());
+
+        let msb = output[self.output_index];
+        let lsb = (msb == self.difficulty_msb).then(|| {
+            let mut output = [0u64; 2];
+            self.lazy_cipher
+                .aes
+                .encrypt_block_b2b(&label.into(), bytemuck::cast_slice_mut(&mut output).into());
+            output[0].to_le() & 0x00ff_ffff_ffff_ffff
+        });
+        LabelReport {
+            index,
+            label,
+            msb,
+            lsb,
+        }
+    }
+
+    fn verify(&self, report: &LabelReport) -> Result<(), Error> {
+        match report.msb.cmp(&self.difficulty_msb) {
+            Ordering::Less => Ok(()),
+            Ordering::Greater => Err(Error::InvalidMsb {
+                index: report.index,
+                msb: report.msb,
+                difficulty_msb: self.difficulty_msb,
+                label: report.label,
+            }),
+            Ordering::Equal => {
+                let lsb = report
+                    .lsb
+                    .expect("LSB is calculated for MSB equal to difficulty");
+                if lsb >= self.difficulty_lsb {
+                    return Err(Error::InvalidLsb {
+                        index: report.index,
+                        lsb,
+                        difficulty_lsb: self.difficulty_lsb,
+                        label: report.label,
+                    });
                 }
+                Ok(())
             }
-            Ok(())
-        })
+        }
     }
 }
 
+fn select_k3_indices(
+    proof: &Proof,
+    challenge: &[u8; 32],
+    indices: Vec<u64>,
+    k3: u32,
+) -> impl Iterator<Item = u64> {
+    let seed = &[
+        challenge.as_slice(),
+        &proof.nonce.to_le_bytes(),
+        proof.indices.as_ref(),
+        &proof.pow.to_le_bytes(),
+    ];
+    RandomValuesIterator::new(indices, seed).take(k3 as usize)
+}
+
 fn next_multiple_of(n: usize, mult: usize) -> usize {
     let r = n % mult;
     if r == 0 {
@@ -298,7 +540,7 @@ mod tests {
         pow_verifier
             .expect_verify()
             .returning(|_, _, _, _, _| Err(crate::pow::Error::InvalidPoW));
-        let verifier = Verifier::new(pow_verifier);
+        let verifier = Verifier::new(pow_verifier, 1).unwrap();
         let result = verifier.verify(
             &Proof {
                 nonce: 0,
@@ -312,6 +554,151 @@ mod tests {
         assert!(matches!(result, Err(Error::InvalidPoW(_))));
     }
 
+    #[test]
+    fn verify_many_reports_result_per_proof() {
+        let cfg = ProofConfig {
+            k1: 3,
+            k2: 3,
+            k3: 3,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(2, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0; 32],
+            commitment_atx_id: [0; 32],
+            challenge: [0; 32],
+            num_units: 10,
+        };
+        let mut pow_verifier = Box::new(MockPowVerifier::new());
+        pow_verifier
+            .expect_verify()
+            .returning(|pow, _, _, _, _| match pow {
+                0 => Err(crate::pow::Error::InvalidPoW),
+                _ => Ok(()),
+            });
+        let verifier = Verifier::new(pow_verifier, 2).unwrap();
+
+        let proof = |pow| Proof {
+            nonce: 0,
+            indices: Cow::from(vec![1, 2, 3]),
+            pow,
+        };
+        let proofs = [
+            (proof(0), metadata.clone()),
+            (proof(1), metadata.clone()),
+            (
+                proof(0),
+                ProofMetadata {
+                    num_units: 11,
+                    ..metadata
+                },
+            ),
+        ];
+        let results = verifier.verify_many(&proofs, &cfg, &init_cfg);
+        assert_eq!(3, results.len());
+        assert!(matches!(results[0], Err(Error::InvalidPoW(_))));
+        assert!(matches!(results[1], Err(Error::InvalidIndicesLen { .. })));
+        assert!(matches!(results[2], Err(Error::InvalidMetadata(_))));
+    }
+
+    #[test]
+    fn report_lists_all_failures() {
+        let cfg = ProofConfig {
+            k1: 3,
+            k2: 3,
+            k3: 3,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(2, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0; 32],
+            commitment_atx_id: [0; 32],
+            challenge: [0; 32],
+            num_units: 11,
+        };
+        let mut pow_verifier = Box::new(MockPowVerifier::new());
+        pow_verifier
+            .expect_verify()
+            .returning(|_, _, _, _, _| Err(crate::pow::Error::InvalidPoW));
+        let verifier = Verifier::new(pow_verifier, 1).unwrap();
+
+        let report = verifier.verify_report(
+            &Proof {
+                nonce: 0,
+                indices: Cow::from(vec![1, 2, 3]),
+                pow: 0,
+            },
+            &metadata,
+            &cfg,
+            &init_cfg,
+        );
+        assert!(!report.is_valid());
+        assert!(matches!(report.failures[0], Error::InvalidMetadata(_)));
+        assert!(matches!(report.failures[1], Error::InvalidPoW(_)));
+        assert!(matches!(
+            report.failures[2],
+            Error::InvalidIndicesLen {
+                expected: 6,
+                got: 3
+            }
+        ));
+        // 3 bytes hold only one 15-bit index
+        assert_eq!(1, report.indices.len());
+        assert_eq!(1, report.k3.len());
+        assert!(report.difficulty.is_some());
+    }
+
+    #[test]
+    fn report_stops_on_zero_units() {
+        let cfg = ProofConfig {
+            k1: 3,
+            k2: 3,
+            k3: 3,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(2, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0; 32],
+            commitment_atx_id: [0; 32],
+            challenge: [0; 32],
+            num_units: 0,
+        };
+        // The PoW isn't checked
+        let verifier = Verifier::new(Box::new(MockPowVerifier::new()), 1).unwrap();
+
+        let report = verifier.verify_report(
+            &Proof {
+                nonce: 0,
+                indices: Cow::from(vec![1, 2, 3]),
+                pow: 0,
+            },
+            &metadata,
+            &cfg,
+            &init_cfg,
+        );
+        assert_eq!(2, report.failures.len());
+        assert!(matches!(report.failures[0], Error::InvalidMetadata(_)));
+        assert!(matches!(report.failures[1], Error::InvalidNumLabels(_)));
+        assert!(report.pow_difficulty.is_none());
+        assert!(report.indices.is_empty());
+        assert!(report.k3.is_empty());
+    }
+
     #[test]
     fn reject_invalid_proof() {
         let pcfg = ProofConfig {
@@ -337,7 +724,7 @@ mod tests {
         pow_verifier
             .expect_verify()
             .returning(|_, _, _, _, _| Ok(()));
-        let verifier = Verifier::new(pow_verifier);
+        let verifier = Verifier::new(pow_verifier, 1).unwrap();
         {
             let empty_proof = Proof {
                 nonce: 0,
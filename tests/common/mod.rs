@@ -0,0 +1,38 @@
+//! Fixture shared by the proving tests.
+use std::path::Path;
+
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    initialize::{CpuInitializer, Initialize},
+    metadata::PostMetadata,
+};
+
+pub const CHALLENGE: &[u8; 32] = b"hello world, challenge me!!!!!!!";
+
+/// Initialize 31 units of POS data in `datadir`, cheap enough to find a proof quickly.
+pub fn init_post(datadir: &Path) -> (PostMetadata, ProofConfig, InitConfig) {
+    let cfg = ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir,
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            31,
+            1000,
+            None,
+        )
+        .unwrap();
+    (metadata, cfg, init_cfg)
+}
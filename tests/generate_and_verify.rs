@@ -1,64 +1,122 @@
-use std::sync::atomic::AtomicBool;
+use std::{
+    ops::Range,
+    sync::{atomic::AtomicBool, Mutex},
+};
 
 use post::{
+    checkpoint::{self, Checkpoint},
     config::{InitConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
+    layout::{self, Layout},
     metadata::ProofMetadata,
+    pow::cache::{CacheKey, InMemoryCache, PowCache},
     pow::randomx::{PoW, RandomXFlag},
-    prove::generate_proof,
+    prove::{
+        generate_proof, merge_partial_proofs, prove_range, Nonces, ProgressReporter, Proof,
+        Prover8_56, ProvingOptions, ProvingParams,
+    },
+    reader::ReadSettings,
     verification::Verifier,
 };
-use tempfile::tempdir;
+use tempfile::{tempdir, TempDir};
+
+use common::{init_post, CHALLENGE};
+
+mod common;
 
 #[test]
 fn test_generate_and_verify() {
     // Initialize some data
-    let challenge = b"hello world, challenge me!!!!!!!";
+    let challenge = CHALLENGE;
     let datadir = tempdir().unwrap();
 
-    let cfg = post::config::ProofConfig {
-        k1: 23,
-        k2: 32,
-        k3: 10,
-        pow_difficulty: [0xFF; 32],
-    };
-    let init_cfg = InitConfig {
-        min_num_units: 1,
-        max_num_units: 1000,
-        labels_per_unit: 256 * 16,
-        scrypt: ScryptParams::new(2, 1, 1),
-    };
-
-    let metadata = CpuInitializer::new(init_cfg.scrypt)
-        .initialize(
-            datadir.path(),
-            &[77; 32],
-            &[0u8; 32],
-            init_cfg.labels_per_unit,
-            31,
-            1000,
-            None,
-        )
-        .unwrap();
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
 
     let pow_flags = RandomXFlag::get_recommended_flags();
     // Generate a proof
     let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
 
     // Verify the proof
     let metadata = ProofMetadata::new(metadata, *challenge);
-    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
     verifier
         .verify(&proof, &metadata, &cfg, &init_cfg)
         .expect("proof should be valid");
 
+    let report = verifier.verify_report(&proof, &metadata, &cfg, &init_cfg);
+    assert!(report.is_valid(), "{:?}", report.failures);
+    assert_eq!(cfg.k2 as usize, report.indices.len());
+    assert_eq!(cfg.k3 as usize, report.k3.len());
+
     // Check that the proof is invalid if we modify one index
-    let mut invalid_proof = proof;
+    let mut invalid_proof = proof.clone();
     invalid_proof.pow -= 1;
     verifier
         .verify(&invalid_proof, &metadata, &cfg, &init_cfg)
         .expect_err("proof should be invalid");
+
+    // Verify both in a batch
+    let proofs = [(proof, metadata.clone()), (invalid_proof, metadata)];
+    let results = verifier.verify_many(&proofs, &cfg, &init_cfg);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+}
+
+#[rstest::rstest]
+#[case::single_file(|disks: &[TempDir]| Layout::SingleFile {
+    path: disks[0].path().join("disk.bin"),
+    offset: 512,
+})]
+#[case::striped(|disks: &[TempDir]| {
+    Layout::striped(disks.iter().map(|d| d.path().into()).collect(), 127)
+})]
+fn test_generate_and_verify_layout(#[case] layout: fn(&[TempDir]) -> Layout) {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+    let disks = [tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap()];
+    layout::save(datadir.path(), &layout(&disks)).unwrap();
+
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            // Small (unaligned) batches read concurrently across file boundaries
+            read: ReadSettings {
+                batch_size: 256,
+                queue_depth: 8,
+                direct_io: true,
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
 }
 
 #[test]
@@ -66,7 +124,7 @@ fn test_generate_and_verify() {
 /// triggers different conditionals in the verifier.
 fn test_generate_and_verify_difficulty_msb_not_zero() {
     // Initialize some data
-    let challenge = b"hello world, challenge me!!!!!!!";
+    let challenge = CHALLENGE;
     let datadir = tempdir().unwrap();
 
     let cfg = post::config::ProofConfig {
@@ -97,19 +155,471 @@ fn test_generate_and_verify_difficulty_msb_not_zero() {
     let pow_flags = RandomXFlag::get_recommended_flags();
     // Generate a proof
     let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
 
     // Verify the proof
     let metadata = ProofMetadata::new(metadata, *challenge);
-    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    let mut invalid_proof = proof.clone();
+    invalid_proof.indices.to_mut()[0] += 1;
+
+    // Regenerating K3 labels serially and in parallel
+    for threads in [1, 4] {
+        let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), threads).unwrap();
+        verifier
+            .verify(&proof, &metadata, &cfg, &init_cfg)
+            .expect("proof should be valid");
+
+        // Check that the proof is invalid if we modify one index
+        verifier
+            .verify(&invalid_proof, &metadata, &cfg, &init_cfg)
+            .expect_err("proof should be invalid");
+    }
+}
+
+#[test]
+fn test_generate_proof_resumes_from_checkpoint() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    // Pretend that a previous run went through all the data with the first 16 nonces
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let previous = Checkpoint {
+        position: metadata.total_size(),
+        ..Checkpoint::new(*challenge, &params, 0..16)
+    };
+    checkpoint::save(datadir.path(), &previous).unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let stop = AtomicBool::new(false);
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(16),
+            threads: 1,
+            checkpoint: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(proof.nonce >= 16);
+    // The checkpoint is removed once the proof is found
+    assert!(checkpoint::load(datadir.path()).unwrap().is_none());
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
     verifier
         .verify(&proof, &metadata, &cfg, &init_cfg)
         .expect("proof should be valid");
+}
 
-    // Check that the proof is invalid if we modify one index
-    let mut invalid_proof = proof;
-    invalid_proof.indices.to_mut()[0] += 1;
+#[derive(Default)]
+struct RecordingReporter {
+    nonces: Mutex<Option<usize>>,
+    passes: Mutex<Vec<(usize, Range<u32>)>>,
+    k2pows: Mutex<Vec<u32>>,
+    read: Mutex<Vec<(u64, u64)>>,
+    result: Mutex<Option<Result<Proof<'static>, String>>>,
+}
+
+impl ProgressReporter for RecordingReporter {
+    fn nonces_picked(&self, nonces: usize) {
+        *self.nonces.lock().unwrap() = Some(nonces);
+    }
+
+    fn new_pass(&self, pass: usize, nonces: Range<u32>) {
+        self.passes.lock().unwrap().push((pass, nonces));
+    }
+
+    fn k2pow_finished(&self, nonce_group: u32, _pow: u64) {
+        self.k2pows.lock().unwrap().push(nonce_group);
+    }
+
+    fn read_progress(&self, read: u64, total: u64) {
+        self.read.lock().unwrap().push((read, total));
+    }
+
+    fn finished(&self, result: Result<&Proof<'static>, &eyre::Report>) {
+        let result = result.cloned().map_err(|e| e.to_string());
+        *self.result.lock().unwrap() = Some(result);
+    }
+}
+
+#[test]
+fn test_generate_proof_reports_progress() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+
+    let (metadata, cfg, _) = init_post(datadir.path());
+
+    let reporter = RecordingReporter::default();
+    let stop = AtomicBool::new(false);
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        stop,
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            reporter: &reporter,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let passes = reporter.passes.into_inner().unwrap();
+    assert_eq!((0, 0..32), passes[0]);
+    let k2pows = reporter.k2pows.into_inner().unwrap();
+    assert_eq!(vec![0, 1], k2pows[..2]);
+    assert_eq!(passes.len() * 2, k2pows.len());
+
+    let total = metadata.total_size();
+    let read = reporter.read.into_inner().unwrap();
+    assert!(!read.is_empty());
+    assert!(read.iter().all(|&(r, t)| t == total && r <= total));
+
+    assert_eq!(Some(Ok(proof)), reporter.result.into_inner().unwrap());
+}
+
+#[test]
+fn test_generate_proof_with_passes_sharing_read() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    let reporter = RecordingReporter::default();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: Nonces::Fixed(16),
+            max_passes_per_read: 3,
+            threads: 1,
+            reporter: &reporter,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // k2pow of all passes is calculated before reading
+    let passes = reporter.passes.into_inner().unwrap();
+    assert_eq!((0, 0..48), passes[0]);
+    let k2pows = reporter.k2pows.into_inner().unwrap();
+    assert_eq!(vec![0, 1, 2], k2pows[..3]);
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
     verifier
-        .verify(&invalid_proof, &metadata, &cfg, &init_cfg)
-        .expect_err("proof should be invalid");
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_proof_picking_nonces() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    let reporter = RecordingReporter::default();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: Nonces::Auto,
+            threads: 1,
+            reporter: &reporter,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let nonces = reporter.nonces.into_inner().unwrap().unwrap();
+    assert_eq!(0, nonces % 16);
+    let passes = reporter.passes.into_inner().unwrap();
+    assert_eq!((0, 0..nonces as u32), passes[0]);
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_proof_resuming_keeps_picked_nonces() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    // A previous run picked 48 nonces and went through all the data with them
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let previous = Checkpoint {
+        position: metadata.total_size(),
+        ..Checkpoint::new(*challenge, &params, 0..48)
+    };
+    checkpoint::save(datadir.path(), &previous).unwrap();
+
+    let reporter = RecordingReporter::default();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: Nonces::Auto,
+            threads: 1,
+            checkpoint: true,
+            reporter: &reporter,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // Not picked again
+    assert_eq!(None, reporter.nonces.into_inner().unwrap());
+    let passes = reporter.passes.into_inner().unwrap();
+    assert_eq!(vec![(0, 0..48), (1, 48..96)], passes[..2]);
+    assert!(proof.nonce >= 48);
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_proof_discards_checkpoint_with_other_nonces() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    // A previous run went through all the data with 48 nonces per pass
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let previous = Checkpoint {
+        position: metadata.total_size(),
+        ..Checkpoint::new(*challenge, &params, 48..96)
+    };
+    checkpoint::save(datadir.path(), &previous).unwrap();
+
+    let reporter = RecordingReporter::default();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: Nonces::Fixed(32),
+            threads: 1,
+            checkpoint: true,
+            reporter: &reporter,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // Started over with the new number of nonces
+    let passes = reporter.passes.into_inner().unwrap();
+    assert_eq!((0, 0..32), passes[0]);
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_proof_in_last_window_of_nonces() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    // 160 doesn't divide the 4096 nonces, the last window 4000..4096
+    // starts at the nonce group 250, not a multiple of its 6 groups.
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let previous = Checkpoint {
+        position: metadata.total_size(),
+        ..Checkpoint::new(*challenge, &params, 3840..4000)
+    };
+    checkpoint::save(datadir.path(), &previous).unwrap();
+
+    let reporter = RecordingReporter::default();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        &PoW::new(pow_flags).unwrap(),
+        AtomicBool::new(false),
+        ProvingOptions {
+            nonces: Nonces::Fixed(160),
+            threads: 1,
+            checkpoint: true,
+            reporter: &reporter,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let passes = reporter.passes.into_inner().unwrap();
+    assert_eq!((25, 4000..4096), passes[1]);
+    assert!(proof.nonce >= 4000);
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_merging_proofs_of_label_ranges() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+
+    let (metadata, cfg, init_cfg) = init_post(datadir.path());
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let prover = Prover8_56::new(
+        challenge,
+        0..32,
+        params,
+        &PoW::new(pow_flags).unwrap(),
+        &metadata.node_id,
+    )
+    .unwrap();
+
+    // Scan the data in 3 slices, as if done by separate machines
+    let source = layout::open_source(datadir.path()).unwrap();
+    let total = metadata.total_labels();
+    let slice = total / 3 / 8 * 8;
+    let stop = AtomicBool::new(false);
+    let partials = [0..slice, slice..2 * slice, 2 * slice..total]
+        .into_iter()
+        .map(|labels| {
+            prove_range(
+                source.as_ref(),
+                &metadata,
+                &prover,
+                labels,
+                cfg.k2,
+                ReadSettings::default(),
+                1,
+                &stop,
+            )
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(2 * slice..total, partials[2].labels);
+
+    // Misaligned ranges are rejected
+    let result = prove_range(
+        source.as_ref(),
+        &metadata,
+        &prover,
+        1..slice,
+        cfg.k2,
+        ReadSettings::default(),
+        1,
+        &stop,
+    );
+    assert!(result.is_err(), "range should be misaligned");
+
+    let proof =
+        merge_partial_proofs(&partials, &prover, cfg.k2, total).expect("proof should be found");
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()), 1).unwrap();
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+
+    // Not enough indices without the other slices
+    let mut first = partials[0].clone();
+    first
+        .indices
+        .values_mut()
+        .for_each(|indices| indices.truncate(1));
+    assert!(merge_partial_proofs([&first], &prover, cfg.k2, total).is_none());
+}
+
+#[test]
+fn test_generate_proof_with_pow_cache() {
+    let challenge = CHALLENGE;
+    let datadir = tempdir().unwrap();
+
+    let (metadata, cfg, _) = init_post(datadir.path());
+
+    let cache = InMemoryCache::default();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let generate = || {
+        generate_proof(
+            datadir.path(),
+            challenge,
+            cfg,
+            &PoW::new(pow_flags).unwrap(),
+            AtomicBool::new(false),
+            ProvingOptions {
+                nonces: Nonces::Fixed(32),
+                threads: 1,
+                // Read in order to find the same proof again
+                read: ReadSettings {
+                    queue_depth: 1,
+                    ..Default::default()
+                },
+                pow_cache: Some(&cache),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+    let proof = generate();
+
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let key = CacheKey {
+        challenge: challenge[..8].try_into().unwrap(),
+        nonce_group: (proof.nonce / 16) as u8,
+        difficulty: params.pow_difficulty,
+        miner_id: metadata.node_id,
+    };
+    assert_eq!(Some(proof.pow), cache.get(&key));
+
+    // Proving again gives the same proof
+    assert_eq!(proof, generate());
 }
@@ -1,9 +1,9 @@
-use std::io::Write;
+use std::io::{Seek, SeekFrom, Write};
 
 use post::{
     config::ScryptParams,
     initialize::{CpuInitializer, Initialize},
-    pos_verification::verify_files,
+    pos_verification::{check_integrity, verify_files, SamplingSeed, VerificationError},
 };
 
 use tempfile::tempdir;
@@ -19,15 +19,15 @@ fn test_generate_and_verify() {
         .unwrap();
 
     // Verify the data
-    verify_files(datadir.path(), 100.0, None, None, scrypt).unwrap();
-    verify_files(datadir.path(), 1.0, None, None, scrypt).unwrap();
-    verify_files(datadir.path(), 1.0, Some(0), Some(1), scrypt).unwrap();
+    verify_files(datadir.path(), 100.0, None, None, scrypt, None).unwrap();
+    verify_files(datadir.path(), 1.0, None, None, scrypt, None).unwrap();
+    verify_files(datadir.path(), 1.0, Some(0), Some(1), scrypt, None).unwrap();
 
     // Try verification with wrong scrypt params
     let wrong_scrypt = ScryptParams::new(4, 1, 1);
-    assert!(verify_files(datadir.path(), 100.0, None, None, wrong_scrypt).is_err());
-    assert!(verify_files(datadir.path(), 1.0, None, None, wrong_scrypt).is_err());
-    assert!(verify_files(datadir.path(), 100.0, Some(0), Some(0), wrong_scrypt).is_err());
+    assert!(verify_files(datadir.path(), 100.0, None, None, wrong_scrypt, None).is_err());
+    assert!(verify_files(datadir.path(), 1.0, None, None, wrong_scrypt, None).is_err());
+    assert!(verify_files(datadir.path(), 100.0, Some(0), Some(0), wrong_scrypt, None).is_err());
 
     // Modify some data
     let mut file = std::fs::OpenOptions::new()
@@ -37,12 +37,125 @@ fn test_generate_and_verify() {
 
     file.write_all(&[0u8; 16]).unwrap();
 
-    assert!(verify_files(datadir.path(), 100.0, None, None, scrypt).is_err());
-    assert!(verify_files(datadir.path(), 100.0, Some(1), Some(1), scrypt).is_err());
-    assert!(verify_files(datadir.path(), 100.0, None, Some(1), scrypt).is_err());
-    assert!(verify_files(datadir.path(), 100.0, Some(1), None, scrypt).is_err());
+    assert!(verify_files(datadir.path(), 100.0, None, None, scrypt, None).is_err());
+    assert!(verify_files(datadir.path(), 100.0, Some(1), Some(1), scrypt, None).is_err());
+    assert!(verify_files(datadir.path(), 100.0, None, Some(1), scrypt, None).is_err());
+    assert!(verify_files(datadir.path(), 100.0, Some(1), None, scrypt, None).is_err());
 
     // skip corrupted files - pass
-    verify_files(datadir.path(), 100.0, None, Some(0), scrypt).unwrap();
-    verify_files(datadir.path(), 100.0, Some(2), None, scrypt).unwrap();
+    verify_files(datadir.path(), 100.0, None, Some(0), scrypt, None).unwrap();
+    verify_files(datadir.path(), 100.0, Some(2), None, scrypt, None).unwrap();
+}
+
+#[test]
+fn test_verify_reports_invalid_labels_in_all_files() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 31, 700, None)
+        .unwrap();
+
+    // Corrupt two labels in the second file and one in the fifth
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(datadir.path().join("postdata_1.bin"))
+        .unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+    file.seek(SeekFrom::Start(16 * 5)).unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(datadir.path().join("postdata_4.bin"))
+        .unwrap();
+    file.seek(SeekFrom::Start(16 * 3)).unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+
+    match verify_files(datadir.path(), 100.0, None, None, scrypt, None) {
+        Err(VerificationError::InvalidLabels(invalid)) => {
+            assert_eq!(
+                vec![(1, vec![0, 16 * 5]), (4, vec![16 * 3])],
+                invalid.into_iter().collect::<Vec<_>>()
+            );
+        }
+        res => panic!("expected invalid labels, got {res:?}"),
+    }
+}
+
+#[test]
+fn test_check_integrity() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 31, 700, None)
+        .unwrap();
+
+    let report = check_integrity(datadir.path(), 100.0, scrypt, None).unwrap();
+    assert!(report.is_valid());
+    assert_eq!(12, report.files.len());
+    assert_eq!(236 * 16, report.files[11].actual_size);
+
+    // Corrupt two labels in the second file
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(datadir.path().join("postdata_1.bin"))
+        .unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+    file.seek(SeekFrom::Start(16 * 5)).unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+    // Cut the fourth file short, remove the sixth one and add an extra one
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(datadir.path().join("postdata_3.bin"))
+        .unwrap()
+        .set_len(16 * 100)
+        .unwrap();
+    std::fs::remove_file(datadir.path().join("postdata_5.bin")).unwrap();
+    std::fs::write(datadir.path().join("postdata_20.bin"), [0u8; 16]).unwrap();
+
+    let report = check_integrity(datadir.path(), 100.0, scrypt, None).unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(vec![5], report.missing_files);
+    assert_eq!(vec!["postdata_20.bin".to_string()], report.extra_files);
+    assert_eq!(11, report.files.len());
+    assert_eq!(vec![0, 16 * 5], report.files[1].invalid_labels);
+    assert_eq!(700, report.files[1].labels_checked);
+    assert_eq!(16 * 700, report.files[3].expected_size);
+    assert_eq!(16 * 100, report.files[3].actual_size);
+    assert_eq!(100, report.files[3].labels_checked);
+    assert!(report.files[3].invalid_labels.is_empty());
+    assert!(report
+        .files
+        .iter()
+        .filter(|f| f.idx != 1 && f.idx != 3)
+        .all(|f| f.is_valid()));
+}
+
+#[test]
+fn test_seeded_sampling_is_reproducible() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 31, 700, None)
+        .unwrap();
+
+    // Corrupt the whole second file so that every label sampled from it is invalid
+    std::fs::write(datadir.path().join("postdata_1.bin"), [0u8; 16 * 700]).unwrap();
+
+    let sampled = |seed: SamplingSeed| {
+        let report = check_integrity(datadir.path(), 10.0, scrypt, Some(seed)).unwrap();
+        assert_eq!(seed, report.seed);
+        report.files[1].invalid_labels.clone()
+    };
+    let seed = SamplingSeed::from_challenge(b"challenge");
+    assert_eq!(70, sampled(seed).len());
+    assert_eq!(sampled(seed), sampled(seed));
+    assert_ne!(
+        sampled(seed),
+        sampled(SamplingSeed::from_challenge(b"other challenge"))
+    );
+
+    assert_eq!(seed, seed.to_string().parse().unwrap());
 }